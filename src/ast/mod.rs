@@ -0,0 +1,400 @@
+use serde_json::Value;
+
+use crate::span::Span;
+
+
+/// Parsed expression.
+///
+/// Two trees are equal when they have the same structure, wherever they appear in the source.
+#[derive(Debug, Clone)]
+pub struct Ast {
+    /// What the expression does.
+    pub kind: AstKind,
+    /// Location of the expression in the source.
+    pub span: Span,
+}
+
+/// Kinds of expressions.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum AstKind {
+    /// Literal value like `1`, `'text'` or `true`.
+    Literal(Value),
+    /// Integer range `start..end`.
+    Range {
+        /// First integer.
+        start: i64,
+        /// Integer after the last one.
+        end: i64,
+    },
+    /// Array literal `[a, b]`.
+    Array(Vec<Ast>),
+    /// Object literal `{key: value, 'quoted key': value}`.
+    Object(Vec<(String, Ast)>),
+    /// Template string `` `text ${value} text` ``; `strings` has one more item than `values`.
+    Template {
+        /// Text around the placeholders.
+        strings: Vec<String>,
+        /// Placeholder expressions, converted to strings when evaluated.
+        values: Vec<Ast>,
+    },
+    /// Name looked up in the contexts.
+    Ident(String),
+    /// Operator applied to one operand, like `-x`.
+    Unary {
+        /// Operator.
+        op: UnaryOp,
+        /// Operand.
+        operand: Box<Ast>,
+    },
+    /// Operator applied to two operands, like `a + b`.
+    Binary {
+        /// Operator.
+        op: BinaryOp,
+        /// Left operand.
+        lhs: Box<Ast>,
+        /// Right operand.
+        rhs: Box<Ast>,
+    },
+    /// Projection `object[*]rest`: `rest` is applied to every item of the array, with `Current`
+    /// standing for the item. Null results are dropped and nested projections are flattened.
+    Projection {
+        /// Projected array.
+        object: Box<Ast>,
+        /// Postfix operators applied to each item, or just `Current`.
+        rest: Box<Ast>,
+        /// Written `object?.[*]`: a wrong type yields null instead of an error.
+        optional: bool,
+    },
+    /// The item being projected, only found in the `rest` of a `Projection`.
+    Current,
+    /// Bindings `let name = value; ... body`: each value sees the earlier names, and `body` is
+    /// evaluated with all of them set.
+    Let {
+        /// Variable names and their values, in source order. Names shadow the contexts.
+        bindings: Vec<(String, Ast)>,
+        /// Expression using the variables.
+        body: Box<Ast>,
+    },
+    /// Conditional `condition ? then : otherwise`; only the selected branch is evaluated.
+    Conditional {
+        /// Boolean condition.
+        condition: Box<Ast>,
+        /// Value when the condition is true.
+        then: Box<Ast>,
+        /// Value when the condition is false or null.
+        otherwise: Box<Ast>,
+    },
+    /// Function call `name(args)`.
+    Call {
+        /// Function name.
+        name: String,
+        /// Arguments.
+        args: Vec<Ast>,
+    },
+    /// Method call `object.name(args)`, calling `name(object, args)`. Without arguments it falls
+    /// back to an existing `name` field of an object when no such function exists.
+    Method {
+        /// Value passed as the first argument.
+        object: Box<Ast>,
+        /// Function name.
+        name: String,
+        /// Remaining arguments.
+        args: Vec<Ast>,
+        /// Written `object?.name(args)`: a null object yields null without calling. Unlike the other
+        /// optional operators, an object of the wrong type is still passed to the function, and
+        /// its errors are kept, since they can not be told apart from other failures.
+        optional: bool,
+    },
+    /// Field access `object.name`.
+    Member {
+        /// Accessed value.
+        object: Box<Ast>,
+        /// Field name.
+        name: String,
+        /// Written `object?.name`: a wrong type yields null instead of an error.
+        optional: bool,
+    },
+    /// Index access `object[index]`.
+    Index {
+        /// Accessed value.
+        object: Box<Ast>,
+        /// Key, or position that is negative from the end.
+        index: Box<Ast>,
+        /// Written `object?.[index]`: a wrong type yields null instead of an error.
+        optional: bool,
+    },
+    /// Slice `object[start:end:step]`; every bound may be omitted.
+    Slice {
+        /// Sliced array or string.
+        object: Box<Ast>,
+        /// First position, negative from the end.
+        start: Option<Box<Ast>>,
+        /// Position after the last one, negative from the end.
+        end: Option<Box<Ast>>,
+        /// Distance between positions, backwards when negative.
+        step: Option<Box<Ast>>,
+        /// Written `object?.[start:end]`: a wrong type yields null instead of an error.
+        optional: bool,
+    },
+}
+
+/// Prefix operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum UnaryOp {
+    /// `!`
+    Not,
+    /// `-`
+    Neg,
+    /// `+`
+    Pos,
+    /// `~`
+    BitNot,
+}
+
+/// Infix operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BinaryOp {
+    /// `+`
+    Add,
+    /// `-`
+    Sub,
+    /// `*`
+    Mul,
+    /// `/`
+    Div,
+    /// `%`
+    Rem,
+    /// `**`
+    Pow,
+    /// `//`
+    FloorDiv,
+    /// `==`
+    Eq,
+    /// `!=`
+    Ne,
+    /// `>`
+    Gt,
+    /// `<`
+    Lt,
+    /// `>=`
+    Ge,
+    /// `<=`
+    Le,
+    /// `=~`
+    Match,
+    /// `!~`
+    NotMatch,
+    /// `&`
+    BitAnd,
+    /// `|`
+    BitOr,
+    /// `^`
+    BitXor,
+    /// `<<`
+    Shl,
+    /// `>>`
+    Shr,
+    /// `in`
+    In,
+    /// `not in`
+    NotIn,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `??`, the right operand is only evaluated when the left one is null.
+    Coalesce,
+}
+
+impl Ast {
+    /// Create an expression.
+    pub fn new(kind: AstKind, span: Span) -> Ast {
+        Ast { kind, span }
+    }
+
+    /// Direct subexpressions, in source order.
+    pub fn children(&self) -> Vec<&Ast> {
+        match self.kind {
+            AstKind::Literal(_) | AstKind::Range { .. } | AstKind::Ident(_) | AstKind::Current => {
+                Vec::new()
+            }
+            AstKind::Array(ref items) => items.iter().collect(),
+            AstKind::Object(ref entries) => entries.iter().map(|entry| &entry.1).collect(),
+            AstKind::Template { ref values, .. } => values.iter().collect(),
+            AstKind::Unary { ref operand, .. } => vec![operand],
+            AstKind::Binary { ref lhs, ref rhs, .. } => vec![lhs, rhs],
+            AstKind::Conditional { ref condition, ref then, ref otherwise } => {
+                vec![condition, then, otherwise]
+            }
+            AstKind::Call { ref args, .. } => args.iter().collect(),
+            AstKind::Method { ref object, ref args, .. } => {
+                std::iter::once(&**object).chain(args.iter()).collect()
+            }
+            AstKind::Member { ref object, .. } => vec![object],
+            AstKind::Index { ref object, ref index, .. } => vec![object, index],
+            AstKind::Projection { ref object, ref rest, .. } => vec![object, rest],
+            AstKind::Let { ref bindings, ref body } => {
+                bindings.iter().map(|binding| &binding.1).chain(std::iter::once(&**body)).collect()
+            }
+            AstKind::Slice { ref object, ref start, ref end, ref step, .. } => {
+                let bounds = [start, end, step];
+                let mut children = vec![&**object];
+                children.extend(bounds.iter().filter_map(|bound| bound.as_deref()));
+                children
+            }
+        }
+    }
+
+    /// Direct subexpressions, in source order.
+    pub fn children_mut(&mut self) -> Vec<&mut Ast> {
+        match self.kind {
+            AstKind::Literal(_) | AstKind::Range { .. } | AstKind::Ident(_) | AstKind::Current => {
+                Vec::new()
+            }
+            AstKind::Array(ref mut items) => items.iter_mut().collect(),
+            AstKind::Object(ref mut entries) => entries.iter_mut().map(|entry| &mut entry.1).collect(),
+            AstKind::Template { ref mut values, .. } => values.iter_mut().collect(),
+            AstKind::Unary { ref mut operand, .. } => vec![operand],
+            AstKind::Binary { ref mut lhs, ref mut rhs, .. } => vec![lhs, rhs],
+            AstKind::Conditional { ref mut condition, ref mut then, ref mut otherwise } => {
+                vec![condition, then, otherwise]
+            }
+            AstKind::Call { ref mut args, .. } => args.iter_mut().collect(),
+            AstKind::Method { ref mut object, ref mut args, .. } => {
+                std::iter::once(&mut **object).chain(args.iter_mut()).collect()
+            }
+            AstKind::Member { ref mut object, .. } => vec![object],
+            AstKind::Index { ref mut object, ref mut index, .. } => vec![object, index],
+            AstKind::Projection { ref mut object, ref mut rest, .. } => vec![object, rest],
+            AstKind::Let { ref mut bindings, ref mut body } => {
+                let values = bindings.iter_mut().map(|binding| &mut binding.1);
+                values.chain(std::iter::once(&mut **body)).collect()
+            }
+            AstKind::Slice { ref mut object, ref mut start, ref mut end, ref mut step, .. } => {
+                let mut children = vec![&mut **object];
+                for bound in [start, end, step] {
+                    children.extend(bound.as_deref_mut());
+                }
+                children
+            }
+        }
+    }
+
+    /// Call `visitor` on this expression.
+    pub fn accept<V: Visitor + ?Sized>(&self, visitor: &mut V) {
+        visitor.visit(self);
+    }
+
+    /// Call `visitor` on this expression, allowing it to rewrite the tree.
+    pub fn accept_mut<V: VisitorMut + ?Sized>(&mut self, visitor: &mut V) {
+        visitor.visit_mut(self);
+    }
+}
+
+impl PartialEq for Ast {
+    fn eq(&self, other: &Ast) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl UnaryOp {
+    /// Source text of the operator.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            UnaryOp::Not => "!",
+            UnaryOp::Neg => "-",
+            UnaryOp::Pos => "+",
+            UnaryOp::BitNot => "~",
+        }
+    }
+}
+
+impl BinaryOp {
+    /// Source text of the operator.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::FloorDiv => "//",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Lt => "<",
+            BinaryOp::Ge => ">=",
+            BinaryOp::Le => "<=",
+            BinaryOp::Match => "=~",
+            BinaryOp::NotMatch => "!~",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::In => "in",
+            BinaryOp::NotIn => "not in",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Coalesce => "??",
+        }
+    }
+}
+
+/// Read-only walk over an expression tree.
+///
+/// The default `visit` visits every subexpression; override it and call `walk` to keep descending.
+///
+/// ```
+/// use resolver::{Ast, AstKind, Expr, Visitor, walk};
+///
+/// struct Idents(Vec<String>);
+///
+/// impl Visitor for Idents {
+///     fn visit(&mut self, ast: &Ast) {
+///         if let AstKind::Ident(ref name) = ast.kind {
+///             self.0.push(name.clone());
+///         }
+///         walk(self, ast);
+///     }
+/// }
+///
+/// let mut idents = Idents(Vec::new());
+/// Expr::new("max(a, b.c) > d[0]").ast().unwrap().accept(&mut idents);
+/// assert_eq!(idents.0, vec!["a", "b", "d"]);
+/// ```
+pub trait Visitor {
+    /// Visit an expression.
+    fn visit(&mut self, ast: &Ast) {
+        walk(self, ast);
+    }
+}
+
+/// Walk that may rewrite an expression tree in place.
+///
+/// The default `visit_mut` visits every subexpression; override it and call `walk_mut` to keep
+/// descending.
+pub trait VisitorMut {
+    /// Visit an expression.
+    fn visit_mut(&mut self, ast: &mut Ast) {
+        walk_mut(self, ast);
+    }
+}
+
+/// Visit the direct subexpressions of `ast`.
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, ast: &Ast) {
+    for child in ast.children() {
+        visitor.visit(child);
+    }
+}
+
+/// Visit the direct subexpressions of `ast`.
+pub fn walk_mut<V: VisitorMut + ?Sized>(visitor: &mut V, ast: &mut Ast) {
+    for child in ast.children_mut() {
+        visitor.visit_mut(child);
+    }
+}
@@ -13,6 +13,11 @@ impl BuiltIn {
         functions.insert("len".to_owned(), create_len_function());
         functions.insert("is_empty".to_owned(), create_is_empty_function());
         functions.insert("array".to_owned(), create_array_function());
+        functions.insert("sum".to_owned(), create_sum_function());
+        functions.insert("lower".to_owned(), create_lower_function());
+        functions.insert("upper".to_owned(), create_upper_function());
+        functions.insert("starts_with".to_owned(), create_starts_with_function());
+        functions.insert("ends_with".to_owned(), create_ends_with_function());
         functions
     }
 }
@@ -115,3 +120,58 @@ fn create_len_function() -> Function {
 fn create_array_function() -> Function {
     Function::new(|values| Ok(to_value(values)))
 }
+
+fn create_sum_function() -> Function {
+    Function::new(|values| {
+        let mut sum = to_value(0);
+        for value in values {
+            match value {
+                Value::Array(array) => {
+                    for value in array {
+                        sum = sum.add(&value)?;
+                    }
+                }
+                value => sum = sum.add(&value)?,
+            }
+        }
+        Ok(sum)
+    })
+}
+
+fn create_lower_function() -> Function {
+    map_string(str::to_lowercase)
+}
+
+fn create_upper_function() -> Function {
+    map_string(str::to_uppercase)
+}
+
+fn map_string(map: fn(&str) -> String) -> Function {
+    Function {
+        max_args: Some(1),
+        min_args: Some(1),
+        compiled: Box::new(move |values| match values[0] {
+            Value::String(ref string) => Ok(to_value(map(string))),
+            ref value => Err(Error::Custom(format!("expected a string, found {:?}", value))),
+        }),
+    }
+}
+
+fn create_starts_with_function() -> Function {
+    test_strings(|string, affix| string.starts_with(affix))
+}
+
+fn create_ends_with_function() -> Function {
+    test_strings(|string, affix| string.ends_with(affix))
+}
+
+fn test_strings(test: fn(&str, &str) -> bool) -> Function {
+    Function {
+        max_args: Some(2),
+        min_args: Some(2),
+        compiled: Box::new(move |values| match (&values[0], &values[1]) {
+            (Value::String(string), Value::String(affix)) => Ok(to_value(test(string, affix))),
+            (a, b) => Err(Error::UnsupportedTypes(format!("{:?}", a), format!("{:?}", b))),
+        }),
+    }
+}
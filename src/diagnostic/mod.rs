@@ -0,0 +1,248 @@
+use std::fmt;
+
+use crate::error::Error;
+use crate::lexer::is_identifier_start;
+use crate::parser::MAX_DEPTH;
+use crate::span::Span;
+
+
+/// Human-readable report of an error, rendered against the source expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Primary message.
+    pub message: String,
+    /// Location of the offending part of the source, if known.
+    pub span: Option<Span>,
+    /// Additional context about the error.
+    pub notes: Vec<String>,
+    /// Suggestions on how to fix the error.
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic with a primary message.
+    pub fn new<T: Into<String>>(message: T) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            span: None,
+            notes: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    /// Set the location of the diagnostic.
+    pub fn span(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    /// Add a note line.
+    pub fn note<T: Into<String>>(mut self, note: T) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Add a help line.
+    pub fn help<T: Into<String>>(mut self, help: T) -> Diagnostic {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Render the diagnostic, underlining its span in `source` with `^` markers. A line that uses
+    /// `//` like a comment also gets a hint to use `#`.
+    pub fn render(&self, source: &str) -> String {
+        let mut output = format!("error: {}\n", self.message);
+
+        // The span may come from a different source, so only use it when it fits this one.
+        let span = self.span.filter(|span| {
+            span.start <= span.end && span.end <= source.len() &&
+                source.is_char_boundary(span.start) && source.is_char_boundary(span.end)
+        });
+        if let Some(span) = span {
+            let line_start = source[..span.start].rfind('\n').map(|index| index + 1).unwrap_or(0);
+            let line_end = source[span.start..]
+                .find('\n')
+                .map(|index| span.start + index)
+                .unwrap_or(source.len());
+            let line = &source[line_start..line_end];
+            let number = span.line.to_string();
+            let gutter = " ".repeat(number.len());
+
+            // Keep tabs so the markers stay aligned with the source line.
+            let indent = source[line_start..span.start]
+                .chars()
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>();
+            let width = source[span.start..span.end.min(line_end)].chars().count().max(1);
+
+            output += &format!("{}--> {}\n", gutter, span);
+            output += &format!("{} |\n", gutter);
+            output += &format!("{} | {}\n", number, line);
+            output += &format!("{} | {}{}\n", gutter, indent, "^".repeat(width));
+
+            for note in &self.notes {
+                output += &format!("{} = note: {}\n", gutter, note);
+            }
+            for help in &self.help {
+                output += &format!("{} = help: {}\n", gutter, help);
+            }
+            if has_slash_comment(&source[line_start..span.end.min(line_end)]) {
+                output += &format!("{} = help: `//` is floor division, start comments with `#`\n", gutter);
+            }
+        } else {
+            for note in &self.notes {
+                output += &format!("= note: {}\n", note);
+            }
+            for help in &self.help {
+                output += &format!("= help: {}\n", help);
+            }
+        }
+
+        output
+    }
+}
+
+/// Whether `text` has `//` before a name, which reads like a comment written in another language.
+fn has_slash_comment(text: &str) -> bool {
+    text.match_indices("//").any(|(index, _)| {
+        let rest = text[index + 2..].trim_start_matches(&[' ', '\t'][..]);
+        rest.chars().next().map_or(false, is_identifier_start)
+    })
+}
+
+impl<'a> From<&'a Error> for Diagnostic {
+    fn from(error: &'a Error) -> Diagnostic {
+        match *error {
+            Error::At(ref error, span) => Diagnostic::from(&**error).span(span),
+            Error::UnsupportedOperator(ref operator) => {
+                Diagnostic::new(format!("unsupported operator `{}`", operator))
+            }
+            Error::CanNotExec(ref operator) => {
+                Diagnostic::new(format!("operator `{:?}` can not be executed", operator))
+            }
+            Error::StartWithNonValueOperator => {
+                Diagnostic::new("expected a value before this operator")
+                    .help("an expression starts with a value, a name, `(`, `[`, `{`, a backtick string or one of `!` `-` `+` `~`")
+            }
+            Error::UnpairedBrackets => {
+                Diagnostic::new("unpaired bracket")
+                    .note("every `(`, `[` and `{` needs a matching `)`, `]` or `}`")
+            }
+            Error::DuplicateValueNode => {
+                Diagnostic::new("expected an operator before this value")
+                    .help("separate values with an operator, e.g. `2 + 3` instead of `2 3`")
+            }
+            Error::DuplicateOperatorNode => {
+                Diagnostic::new("expected a value before this operator")
+                    .help("separate operators with a value, e.g. `2 + 3` instead of `2 + + 3`")
+            }
+            Error::CommaNotWithFunction => {
+                Diagnostic::new("comma outside of a function call")
+                    .help("commas can only separate function arguments, e.g. `max(1, 2)`")
+            }
+            Error::BracketNotWithFunction => {
+                Diagnostic::new("empty brackets")
+                    .help("brackets need an expression inside, e.g. `(a + b)` or `items[0]`; only a call can be empty, e.g. `now()`")
+            }
+            Error::FunctionNotExists(ref ident) => {
+                Diagnostic::new(format!("function `{}` does not exist", ident))
+                    .help("register it with `Expr::function` or `Expr::const_function`")
+            }
+            Error::ExpectedBoolean(ref value) => {
+                Diagnostic::new(format!("expected a boolean, found `{}`", value))
+            }
+            Error::ExpectedIdentifier => Diagnostic::new("expected an identifier"),
+            Error::ExpectedArray => Diagnostic::new("expected an array"),
+            Error::ExpectedObject => Diagnostic::new("expected an object"),
+            Error::ExpectedNumber => Diagnostic::new("expected a number"),
+            Error::NoFinalNode => Diagnostic::new("empty expression"),
+            Error::ArgumentsGreater(max) => {
+                Diagnostic::new("too many arguments")
+                    .note(format!("this function accepts at most {} argument(s)", max))
+            }
+            Error::ArgumentsLess(min) => {
+                Diagnostic::new("not enough arguments")
+                    .note(format!("this function needs at least {} argument(s)", min))
+            }
+            Error::UnsupportedTypes(ref a, ref b) => {
+                Diagnostic::new("unsupported operand types")
+                    .note(format!("left operand is {}", a))
+                    .note(format!("right operand is {}", b))
+            }
+            Error::InvalidRange(ref ident) => {
+                Diagnostic::new(format!("invalid range `{}`", ident))
+                    .help("a range looks like `0..5`")
+            }
+            Error::CanNotAddChild => Diagnostic::new("unexpected token"),
+            Error::UnexpectedEnd => Diagnostic::new("unexpected end of expression, expected a value"),
+            Error::UnterminatedString => {
+                Diagnostic::new("unterminated string literal")
+                    .help("close the string with the same quote it starts with")
+            }
+            Error::UnterminatedComment => {
+                Diagnostic::new("unterminated block comment")
+                    .help("close the comment with `*/`")
+            }
+            Error::InvalidEscape(ref escape) => {
+                Diagnostic::new(format!("invalid escape sequence `{}`", escape))
+                    .help("supported escapes are \\\\, \\', \\\", \\n, \\t, \\r and \\u{XXXX}")
+            }
+            Error::InvalidNumber(ref literal) => {
+                Diagnostic::new(format!("invalid number literal `{}`", literal))
+                    .help("numbers look like `42`, `1_000`, `2.5e-3`, `0xff`, `0o17` or `0b1010`")
+            }
+            Error::ExpectedInteger => Diagnostic::new("expected an integer"),
+            Error::InvalidShift(ref amount) => {
+                Diagnostic::new(format!("invalid shift amount `{}`", amount))
+                    .note("integers can be shifted by 0 to 63 bits")
+            }
+            Error::NotCallable => {
+                Diagnostic::new("this expression can not be called")
+                    .help("only a function name can be called, e.g. `max(1, 2)`")
+            }
+            Error::ZeroStep => Diagnostic::new("slice step cannot be zero"),
+            Error::DivisionByZero => Diagnostic::new("division by zero"),
+            Error::InvalidRegex(ref pattern, ref detail) => {
+                Diagnostic::new(format!("invalid regular expression `{}`", pattern))
+                    .note(detail.clone())
+            }
+            Error::ExpectedToken(ref token) => Diagnostic::new(format!("expected `{}`", token)),
+            Error::UnexpectedCharacter(character) => {
+                Diagnostic::new(format!("unexpected character `{}`", character))
+            }
+            Error::UnterminatedTag => {
+                Diagnostic::new("unterminated template tag")
+                    .help("close `{{` with `}}` and `{%` with `%}`")
+            }
+            Error::InvalidTag(ref tag) => {
+                Diagnostic::new(format!("unexpected template tag `{}`", tag))
+                    .help("blocks are `{% if x %}`, `{% else %}`, `{% endif %}`, `{% for x in xs %}` and `{% endfor %}`")
+            }
+            Error::UnclosedBlock(ref tag) => {
+                Diagnostic::new(format!("`{}` is never closed", tag))
+                    .help("end it with `{% endif %}` or `{% endfor %}`")
+            }
+            Error::TooDeep => {
+                Diagnostic::new("expression is nested too deeply")
+                    .note(format!("at most {} levels of nesting are supported", MAX_DEPTH))
+            }
+            Error::Custom(ref detail) => Diagnostic::new(detail.clone()),
+        }
+    }
+}
+
+impl From<Error> for Diagnostic {
+    fn from(error: Error) -> Diagnostic {
+        Diagnostic::from(&error)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(span) = self.span {
+            write!(f, " ({})", span)?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
 
 use serde_json::Value;
 
+use crate::diagnostic::Diagnostic;
 use crate::operator::Operator;
+use crate::span::Span;
 
 
 quick_error! {
@@ -88,9 +90,112 @@ quick_error! {
         CanNotAddChild {
             display("Can not add child node.")
         }
+        /// The expression ends where a value was expected, like `1 +`.
+        UnexpectedEnd {
+            display("Unexpected end of expression.")
+        }
+        /// A string literal is missing its closing quote.
+        UnterminatedString {
+            display("Unterminated string literal.")
+        }
+        /// A `/*` comment without its closing `*/`.
+        UnterminatedComment {
+            display("Unterminated block comment.")
+        }
+        /// Malformed escape sequence in a string literal.
+        InvalidEscape(escape: String) {
+            display("Invalid escape sequence: {}", escape)
+        }
+        /// Malformed number literal like `1e` or `0xfg`.
+        InvalidNumber(literal: String) {
+            display("Invalid number literal: {}", literal)
+        }
+        /// Expect integer.
+        ExpectedInteger {
+            display("Expected integer.")
+        }
+        /// Shift amount outside of `0..64`.
+        InvalidShift(amount: String) {
+            display("Invalid shift amount: {}", amount)
+        }
+        /// Only a function name can be called, like `f(x)`.
+        NotCallable {
+            display("Only a function name can be called.")
+        }
+        /// Slice with a step of zero, like `a[::0]`.
+        ZeroStep {
+            display("Slice step cannot be zero.")
+        }
+        /// Integer division by zero.
+        DivisionByZero {
+            display("Division by zero.")
+        }
+        /// Invalid regular expression used with `=~` or `!~`.
+        InvalidRegex(pattern: String, detail: String) {
+            display("Invalid regular expression {:?}: {}", pattern, detail)
+        }
+        /// A specific token is missing, like the `:` of a conditional.
+        ExpectedToken(token: String) {
+            display("Expected {}", token)
+        }
+        /// A character that can not start any token.
+        UnexpectedCharacter(character: char) {
+            display("Unexpected character: {:?}", character)
+        }
+        /// A template `{{` or `{%` tag without its closing `}}` or `%}`.
+        UnterminatedTag {
+            display("Unterminated template tag.")
+        }
+        /// Unknown or misplaced template statement, like a stray `{% endif %}`.
+        InvalidTag(tag: String) {
+            display("Invalid template tag: {}", tag)
+        }
+        /// A template block, like `{% if x %}`, without its end tag.
+        UnclosedBlock(tag: String) {
+            display("Unclosed template block: {}", tag)
+        }
+        /// Subexpressions nested deeper than the parser allows.
+        TooDeep {
+            display("Expression is nested too deeply.")
+        }
         /// Custom error.
         Custom(detail: String) {
             display("{}", detail)
         }
+        /// An error located in the source expression.
+        At(error: Box<Error>, span: Span) {
+            display("{} ({})", error, span)
+        }
+    }
+}
+
+impl Error {
+    /// Locate the error at `span`. An error that is already located keeps its span.
+    pub fn at(self, span: Span) -> Error {
+        match self {
+            Error::At(..) => self,
+            error => Error::At(Box::new(error), span),
+        }
+    }
+
+    /// Location of the error in the source expression, if known.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Error::At(_, span) => Some(span),
+            _ => None,
+        }
+    }
+
+    /// Render the error as a diagnostic pointing into `source`.
+    pub fn render(&self, source: &str) -> String {
+        Diagnostic::from(self).render(source)
+    }
+
+    /// The error without its location.
+    pub fn kind(&self) -> &Error {
+        match *self {
+            Error::At(ref error, _) => error,
+            _ => self,
+        }
     }
 }
@@ -12,6 +12,7 @@ use std::fmt;
 
 use crate::function::{StaticFunction, ConstFunction};
 use crate::tree::Tree;
+use crate::ast::Ast;
 use crate::error::Error;
 use crate::{to_value, ConstFunctions};
 use crate::{Function, Functions, Context, Contexts, Compiled, Value};
@@ -72,10 +73,10 @@ impl Expr {
 
     /// Execute the expression.
     pub fn exec(&mut self) -> Result<Value, Error> {
-        if self.compiled.is_none() {
-            Tree::new(self.expression.clone()).compile()?(&self.contexts, &self.functions, Rc::clone(&self.const_functions))
+        if let Some(compiled) = self.compiled.as_ref() {
+            compiled(&self.contexts, &self.functions, Rc::clone(&self.const_functions))
         } else {
-            self.compiled.as_ref().unwrap()(&self.contexts, &self.functions, Rc::clone(&self.const_functions))
+            Tree::new(self.expression.clone()).compile()?(&self.contexts, &self.functions, Rc::clone(&self.const_functions))
         }
     }
 
@@ -83,6 +84,16 @@ impl Expr {
     pub fn get_compiled(&self) -> Option<&Compiled> {
         self.compiled.as_ref()
     }
+
+    /// Parse the expression into its syntax tree.
+    pub fn ast(&self) -> Result<Ast, Error> {
+        Tree::new(self.expression.clone()).parse()
+    }
+
+    /// Format the expression as canonical source text.
+    pub fn format(&self) -> Result<String, Error> {
+        Ok(self.ast()?.to_string())
+    }
 }
 
 impl Clone for Expr {
@@ -155,13 +166,13 @@ impl<'a> ExecOptions<'a> {
     }
 
     /// Set contexts.
-    pub fn contexts(&mut self, contexts: &'a [Context]) -> &'a mut ExecOptions {
+    pub fn contexts(&mut self, contexts: &'a [Context]) -> &'a mut ExecOptions<'_> {
         self.contexts = Some(contexts);
         self
     }
 
     /// Set functions.
-    pub fn functions(&mut self, functions: &'a Functions) -> &'a mut ExecOptions {
+    pub fn functions(&mut self, functions: &'a Functions) -> &'a mut ExecOptions<'_> {
         self.functions = Some(functions);
         self
     }
@@ -171,14 +182,14 @@ impl<'a> ExecOptions<'a> {
         let empty_contexts = create_empty_contexts();
         let empty_functions = Functions::new();
 
-        let contexts = if self.contexts.is_some() {
-            self.contexts.unwrap()
+        let contexts = if let Some(contexts) = self.contexts {
+            contexts
         } else {
             &empty_contexts
         };
 
-        let functions = if self.functions.is_some() {
-            self.functions.unwrap()
+        let functions = if let Some(functions) = self.functions {
+            functions
         } else {
             &empty_functions
         };
@@ -0,0 +1,206 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::ast::{Ast, AstKind, UnaryOp};
+use crate::lexer::is_identifier;
+use crate::operator::{Associativity, Precedence};
+
+
+/// Canonical source text: single spaces around binary operators, single-quoted strings and only
+/// the parentheses the precedence rules require. Parsing the output yields an equal `Ast`.
+impl fmt::Display for Ast {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            AstKind::Literal(ref value) => write_value(formatter, value),
+            AstKind::Range { start, end } => write!(formatter, "{}..{}", start, end),
+            AstKind::Array(ref items) => {
+                formatter.write_str("[")?;
+                write_list(formatter, items.iter(), |formatter, item| write!(formatter, "{}", item))?;
+                formatter.write_str("]")
+            }
+            AstKind::Object(ref entries) => {
+                formatter.write_str("{")?;
+                write_list(formatter, entries.iter(), |formatter, (key, value)| {
+                    write_key(formatter, key)?;
+                    write!(formatter, ": {}", value)
+                })?;
+                formatter.write_str("}")
+            }
+            AstKind::Template { ref strings, ref values } => {
+                formatter.write_str("`")?;
+                for (index, string) in strings.iter().enumerate() {
+                    write_escaped(formatter, string, '`')?;
+                    if let Some(value) = values.get(index) {
+                        write!(formatter, "${{{}}}", value)?;
+                    }
+                }
+                formatter.write_str("`")
+            }
+            AstKind::Ident(ref name) => formatter.write_str(name),
+            AstKind::Unary { op, ref operand } => {
+                // `!~` would lex as the regex operator.
+                let bit_not = matches!(operand.kind, AstKind::Unary { op: UnaryOp::BitNot, .. });
+                let parens = precedence(operand) < Precedence::Prefix || op == UnaryOp::Not && bit_not;
+                formatter.write_str(op.as_str())?;
+                write_operand(formatter, operand, parens)
+            }
+            AstKind::Binary { op, ref lhs, ref rhs } => {
+                let (outer, associativity) = op.binding();
+                let lhs_parens = precedence(lhs) < outer ||
+                    precedence(lhs) == outer && associativity == Associativity::Right;
+                // A prefix operator on the right always takes just its operand, as in `2 ** -1`.
+                let rhs_parens = !matches!(rhs.kind, AstKind::Unary { .. }) &&
+                    (precedence(rhs) < outer ||
+                        precedence(rhs) == outer && associativity == Associativity::Left);
+
+                write_operand(formatter, lhs, lhs_parens)?;
+                write!(formatter, " {} ", op.as_str())?;
+                write_operand(formatter, rhs, rhs_parens)
+            }
+            AstKind::Slice { ref object, ref start, ref end, ref step, optional } => {
+                write_operand(formatter, object, needs_postfix_parens(object))?;
+                formatter.write_str(if optional { "?.[" } else { "[" })?;
+                if let Some(ref start) = *start {
+                    write!(formatter, "{}", start)?;
+                }
+                formatter.write_str(":")?;
+                if let Some(ref end) = *end {
+                    write!(formatter, "{}", end)?;
+                }
+                if let Some(ref step) = *step {
+                    write!(formatter, ":{}", step)?;
+                }
+                formatter.write_str("]")
+            }
+            AstKind::Projection { ref object, ref rest, optional } => {
+                write_operand(formatter, object, needs_postfix_parens(object))?;
+                write!(formatter, "{}[*]{}", if optional { "?." } else { "" }, rest)
+            }
+            AstKind::Current => Ok(()),
+            AstKind::Let { ref bindings, ref body } => {
+                for (name, value) in bindings {
+                    write!(formatter, "let {} = {}; ", name, value)?;
+                }
+                write!(formatter, "{}", body)
+            }
+            AstKind::Conditional { ref condition, ref then, ref otherwise } => {
+                write_operand(formatter, condition, precedence(condition) <= Precedence::Conditional)?;
+                write!(formatter, " ? {} : ", then)?;
+                write_operand(formatter, otherwise, precedence(otherwise) < Precedence::Conditional)
+            }
+            AstKind::Call { ref name, ref args } => {
+                write!(formatter, "{}(", name)?;
+                write_list(formatter, args.iter(), |formatter, arg| write!(formatter, "{}", arg))?;
+                formatter.write_str(")")
+            }
+            AstKind::Method { ref object, ref name, ref args, optional } => {
+                write_operand(formatter, object, needs_postfix_parens(object))?;
+                write!(formatter, "{}.{}(", if optional { "?" } else { "" }, name)?;
+                write_list(formatter, args.iter(), |formatter, arg| write!(formatter, "{}", arg))?;
+                formatter.write_str(")")
+            }
+            AstKind::Member { ref object, ref name, optional } => {
+                write_operand(formatter, object, needs_postfix_parens(object))?;
+                write!(formatter, "{}.{}", if optional { "?" } else { "" }, name)
+            }
+            AstKind::Index { ref object, ref index, optional } => {
+                write_operand(formatter, object, needs_postfix_parens(object))?;
+                write!(formatter, "{}[{}]", if optional { "?." } else { "" }, index)
+            }
+        }
+    }
+}
+
+/// How tightly the expression holds together when it appears as an operand.
+fn precedence(ast: &Ast) -> Precedence {
+    match ast.kind {
+        AstKind::Unary { op, .. } => op.binding().0,
+        AstKind::Binary { op, .. } => op.binding().0,
+        AstKind::Conditional { .. } => Precedence::Conditional,
+        AstKind::Let { .. } => Precedence::Lowest,
+        _ => Precedence::Postfix,
+    }
+}
+
+/// Numbers and ranges would swallow a following `.` in the lexer, and a projection would take
+/// the operator into its rest.
+fn needs_postfix_parens(object: &Ast) -> bool {
+    match object.kind {
+        AstKind::Literal(Value::Number(_)) | AstKind::Range { .. } | AstKind::Projection { .. } => true,
+        _ => precedence(object) < Precedence::Postfix,
+    }
+}
+
+fn write_operand(formatter: &mut fmt::Formatter, ast: &Ast, parens: bool) -> fmt::Result {
+    if parens {
+        write!(formatter, "({})", ast)
+    } else {
+        write!(formatter, "{}", ast)
+    }
+}
+
+fn write_list<I, F>(formatter: &mut fmt::Formatter, items: I, mut write_item: F) -> fmt::Result
+    where I: Iterator,
+          F: FnMut(&mut fmt::Formatter, I::Item) -> fmt::Result
+{
+    for (index, item) in items.enumerate() {
+        if index > 0 {
+            formatter.write_str(", ")?;
+        }
+        write_item(formatter, item)?;
+    }
+    Ok(())
+}
+
+fn write_key(formatter: &mut fmt::Formatter, key: &str) -> fmt::Result {
+    if is_identifier(key) {
+        formatter.write_str(key)
+    } else {
+        write_string(formatter, key)
+    }
+}
+
+fn write_value(formatter: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match *value {
+        Value::String(ref string) => write_string(formatter, string),
+        Value::Array(ref items) => {
+            formatter.write_str("[")?;
+            write_list(formatter, items.iter(), write_value)?;
+            formatter.write_str("]")
+        }
+        Value::Object(ref object) => {
+            formatter.write_str("{")?;
+            write_list(formatter, object.iter(), |formatter, (key, value)| {
+                write_key(formatter, key)?;
+                formatter.write_str(": ")?;
+                write_value(formatter, value)
+            })?;
+            formatter.write_str("}")
+        }
+        ref value => write!(formatter, "{}", value),
+    }
+}
+
+fn write_string(formatter: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    formatter.write_str("'")?;
+    write_escaped(formatter, string, '\'')?;
+    formatter.write_str("'")
+}
+
+/// Writes `string` between `quote`s; inside a template, `${` is escaped too.
+fn write_escaped(formatter: &mut fmt::Formatter, string: &str, quote: char) -> fmt::Result {
+    for (index, c) in string.char_indices() {
+        match c {
+            '\\' => formatter.write_str("\\\\")?,
+            c if c == quote => write!(formatter, "\\{}", c)?,
+            '$' if quote == '`' && string[index + 1..].starts_with('{') => formatter.write_str("\\$")?,
+            '\n' => formatter.write_str("\\n")?,
+            '\t' => formatter.write_str("\\t")?,
+            '\r' => formatter.write_str("\\r")?,
+            c if c.is_control() => write!(formatter, "\\u{{{:x}}}", c as u32)?,
+            c => write!(formatter, "{}", c)?,
+        }
+    }
+    Ok(())
+}
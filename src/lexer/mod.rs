@@ -0,0 +1,470 @@
+use std::str::FromStr;
+
+use unicode_ident::{is_xid_continue, is_xid_start};
+
+use crate::error::Error;
+use crate::operator::{Operator, Token};
+use crate::span::Span;
+use crate::to_value;
+
+
+/// Splits an expression into tokens. Offsets are byte offsets into the source and always fall on
+/// character boundaries.
+pub struct Lexer<'a> {
+    source: &'a str,
+    offset: usize,
+    line: usize,
+    column: usize,
+    tokens: Vec<Token>,
+    parentheses: Vec<Span>,
+    /// Opening backtick and open `{` count of each template placeholder being lexed.
+    templates: Vec<(Mark, usize)>,
+}
+
+#[derive(Clone, Copy)]
+struct Mark {
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Lexer<'a> {
+        Lexer {
+            source,
+            offset: 0,
+            line: 1,
+            column: 1,
+            tokens: Vec::new(),
+            parentheses: Vec::new(),
+            templates: Vec::new(),
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<Vec<Token>, Error> {
+        while let Some(cur) = self.peek() {
+            let start = self.mark();
+
+            if is_whitespace(cur) {
+                self.bump();
+            } else if cur == '#' {
+                while self.peek().map_or(false, |cur| cur != '\n') {
+                    self.bump();
+                }
+            } else if cur == '/' && self.peek_nth(1) == Some('*') {
+                self.block_comment(start)?;
+            } else if cur == '"' || cur == '\'' {
+                self.string(start, cur)?;
+            } else if cur == '`' {
+                self.bump();
+                self.push(Operator::Backtick, start);
+                self.template(start)?;
+            } else if cur == '}' && self.templates.last().map_or(false, |&(_, depth)| depth == 0) {
+                let (open, _) = self.templates.pop().unwrap();
+                self.bump();
+                self.push(Operator::RightCurlyBracket, start);
+                self.template(open)?;
+            } else if cur.is_ascii_digit() {
+                self.number(start)?;
+            } else if is_identifier_start(cur) {
+                self.identifier(start)?;
+            } else {
+                self.operator(start, cur)?;
+            }
+        }
+
+        if let Some((open, _)) = self.templates.pop() {
+            Err(Error::UnterminatedString.at(self.span(open)))
+        } else if let Some(span) = self.parentheses.pop() {
+            Err(Error::UnpairedBrackets.at(span))
+        } else {
+            Ok(self.tokens)
+        }
+    }
+
+    fn block_comment(&mut self, start: Mark) -> Result<(), Error> {
+        self.bump();
+        self.bump();
+        while !(self.peek() == Some('*') && self.peek_nth(1) == Some('/')) {
+            if self.bump().is_none() {
+                return Err(Error::UnterminatedComment.at(self.span(start)));
+            }
+        }
+        self.bump();
+        self.bump();
+        Ok(())
+    }
+
+    fn string(&mut self, start: Mark, quote: char) -> Result<(), Error> {
+        self.bump();
+        let mut content = String::new();
+
+        loop {
+            let escape = self.mark();
+            match self.bump() {
+                Some(cur) if cur == quote => break,
+                Some('\\') => content.push(self.escape(escape)?),
+                Some(cur) => content.push(cur),
+                None => return Err(Error::UnterminatedString.at(self.span(start))),
+            }
+        }
+
+        self.push(Operator::Value(to_value(content)), start);
+        Ok(())
+    }
+
+    /// Reads template text up to the closing backtick or the next `${` of the template opened at
+    /// `open`.
+    fn template(&mut self, open: Mark) -> Result<(), Error> {
+        let start = self.mark();
+        let mut content = String::new();
+
+        loop {
+            let mark = self.mark();
+            match self.peek() {
+                Some('`') => {
+                    self.push(Operator::Value(to_value(content)), start);
+                    self.bump();
+                    self.push(Operator::Backtick, mark);
+                    return Ok(());
+                }
+                Some('$') if self.peek_nth(1) == Some('{') => {
+                    self.push(Operator::Value(to_value(content)), start);
+                    self.bump();
+                    self.bump();
+                    self.push(Operator::DollarLeftCurlyBracket, mark);
+                    self.templates.push((open, 0));
+                    return Ok(());
+                }
+                Some('\\') => {
+                    self.bump();
+                    match self.peek() {
+                        Some(cur @ '`') | Some(cur @ '$') => {
+                            self.bump();
+                            content.push(cur);
+                        }
+                        _ => content.push(self.escape(mark)?),
+                    }
+                }
+                Some(cur) => {
+                    self.bump();
+                    content.push(cur);
+                }
+                None => return Err(Error::UnterminatedString.at(self.span(open))),
+            }
+        }
+    }
+
+    /// Reads the rest of an escape sequence whose backslash starts at `start`.
+    fn escape(&mut self, start: Mark) -> Result<char, Error> {
+        let escaped = match self.bump() {
+            Some('\\') => Some('\\'),
+            Some('\'') => Some('\''),
+            Some('"') => Some('"'),
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('u') if self.eat('{') => {
+                let digits_start = self.offset;
+                while self.peek().map_or(false, |cur| cur.is_ascii_hexdigit()) {
+                    self.bump();
+                }
+                let digits = &self.source[digits_start..self.offset];
+
+                if (1..=6).contains(&digits.len()) && self.eat('}') {
+                    u32::from_str_radix(digits, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                }
+            }
+            Some(_) => None,
+            None => return Err(Error::UnterminatedString.at(self.span(start))),
+        };
+
+        escaped.ok_or_else(|| {
+            let raw = self.source[start.offset..self.offset].to_owned();
+            Error::InvalidEscape(raw).at(self.span(start))
+        })
+    }
+
+    fn number(&mut self, start: Mark) -> Result<(), Error> {
+        let radix = match (self.peek(), self.peek_nth(1)) {
+            (Some('0'), Some('x')) | (Some('0'), Some('X')) => 16,
+            (Some('0'), Some('o')) | (Some('0'), Some('O')) => 8,
+            (Some('0'), Some('b')) | (Some('0'), Some('B')) => 2,
+            _ => 10,
+        };
+
+        let value = if radix == 10 {
+            self.digits(10);
+            if self.peek() == Some('.') && self.peek_nth(1) == Some('.') {
+                return self.range(start);
+            }
+
+            let mut float = false;
+            if self.peek() == Some('.') && self.peek_nth(1).map_or(false, |cur| cur.is_ascii_digit()) {
+                self.bump();
+                self.digits(10);
+                float = true;
+            }
+            if let Some('e') | Some('E') = self.peek() {
+                let sign = matches!(self.peek_nth(1), Some('+') | Some('-')) as usize;
+                if self.peek_nth(1 + sign).map_or(false, |cur| cur.is_ascii_digit()) {
+                    for _ in 0..=sign {
+                        self.bump();
+                    }
+                    self.digits(10);
+                    float = true;
+                }
+            }
+            self.end_number(start, 10)?;
+
+            let raw = self.source[start.offset..self.offset].replace('_', "");
+            match raw.parse::<u64>() {
+                Ok(n) if !float => Some(to_value(n)),
+                _ => raw.parse::<f64>().ok().filter(|n| n.is_finite()).map(to_value),
+            }
+        } else {
+            self.bump();
+            self.bump();
+            let digits_start = self.offset;
+            self.digits(radix);
+            self.end_number(start, radix)?;
+
+            let digits = self.source[digits_start..self.offset].replace('_', "");
+            u64::from_str_radix(&digits, radix).ok().map(to_value)
+        };
+
+        match value {
+            Some(value) => {
+                self.push(Operator::Value(value), start);
+                Ok(())
+            }
+            None => Err(self.invalid_number(start)),
+        }
+    }
+
+    /// Reads the end of an integer range like `0..5`, whose start has already been read.
+    fn range(&mut self, start: Mark) -> Result<(), Error> {
+        let first = self.source[start.offset..self.offset].replace('_', "");
+        self.bump();
+        self.bump();
+        let end_start = self.offset;
+        self.digits(10);
+        let last = self.source[end_start..self.offset].replace('_', "");
+
+        let glued = self.peek().map_or(false, |cur| cur == '.' || is_identifier_continue(cur));
+        if glued {
+            self.skip_literal();
+        }
+
+        match (first.parse::<i64>(), last.parse::<i64>()) {
+            (Ok(first), Ok(last)) if !glued => {
+                self.push(Operator::Range(first, last), start);
+                Ok(())
+            }
+            _ => {
+                let raw = self.source[start.offset..self.offset].to_owned();
+                Err(Error::InvalidRange(raw).at(self.span(start)))
+            }
+        }
+    }
+
+    fn digits(&mut self, radix: u32) {
+        while self.peek().map_or(false, |cur| cur == '_' || cur.is_digit(radix)) {
+            self.bump();
+        }
+    }
+
+    /// Rejects letters, digits or a fraction directly following a number, and underscores that
+    /// are not followed by a digit.
+    fn end_number(&mut self, start: Mark, radix: u32) -> Result<(), Error> {
+        let raw = &self.source[start.offset..self.offset];
+        let misplaced_underscore = raw.char_indices().any(|(index, cur)| {
+            cur == '_' && !raw[index + 1..].starts_with(|next: char| next == '_' || next.is_digit(radix))
+        });
+        let glued = self.peek().map_or(false, is_identifier_continue)
+            || (self.peek() == Some('.') && self.peek_nth(1).map_or(false, |cur| cur.is_ascii_digit()));
+
+        if glued {
+            self.skip_literal();
+        }
+
+        if misplaced_underscore || glued {
+            Err(self.invalid_number(start))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn skip_literal(&mut self) {
+        while self.peek().map_or(false, |cur| cur == '.' || is_identifier_continue(cur)) {
+            self.bump();
+        }
+    }
+
+    fn invalid_number(&self, start: Mark) -> Error {
+        let raw = self.source[start.offset..self.offset].to_owned();
+        Error::InvalidNumber(raw).at(self.span(start))
+    }
+
+    fn identifier(&mut self, start: Mark) -> Result<(), Error> {
+        self.bump();
+        while self.peek().map_or(false, is_identifier_continue) {
+            self.bump();
+        }
+
+        let raw = &self.source[start.offset..self.offset];
+        let operator = match Operator::from_str(raw)? {
+            Operator::In if self.follows_member_access() => Operator::Identifier(raw.to_owned()),
+            Operator::In if self.follows_not() => {
+                let not = self.tokens.pop().unwrap();
+                let span = not.span.to(self.span(start));
+                self.tokens.push(Token::new(Operator::NotIn, span));
+                return Ok(());
+            }
+            operator => operator,
+        };
+        self.push(operator, start);
+        Ok(())
+    }
+
+    /// Whether the identifier being lexed is a field name, like `in` in `a.in`.
+    fn follows_member_access(&self) -> bool {
+        self.tokens.last().map_or(false, |token| {
+            token.operator == Operator::Dot || token.operator == Operator::QuestionDot
+        })
+    }
+
+    /// Whether the last token is a `not` standing between an operand and `in`.
+    fn follows_not(&self) -> bool {
+        match *self.tokens.as_slice() {
+            [.., ref operand, ref not] => {
+                operand.operator.ends_operand() &&
+                    not.operator == Operator::Identifier("not".to_owned())
+            }
+            _ => false,
+        }
+    }
+
+    fn operator(&mut self, start: Mark, cur: char) -> Result<(), Error> {
+        self.bump();
+
+        let raw = match cur {
+            '!' | '=' | '>' | '<' if self.eat('=') => &self.source[start.offset..self.offset],
+            '!' | '=' if self.eat('~') => &self.source[start.offset..self.offset],
+            '&' | '|' | '?' | '*' | '/' | '<' | '>' if self.eat(cur) => {
+                &self.source[start.offset..self.offset]
+            }
+            '?' if self.eat('.') => &self.source[start.offset..self.offset],
+            '+' | '-' | '*' | '/' | '%' | '(' | ')' | '[' | ']' | ',' | '.' | '!' | '>' | '<' | '?' |
+            ':' | '&' | '|' | '^' | '~' | '{' | '}' | '=' | ';' => {
+                &self.source[start.offset..self.offset]
+            }
+            _ => return Err(Error::UnexpectedCharacter(cur).at(self.span(start))),
+        };
+
+        let operator = match Operator::from_str(raw)? {
+            Operator::Sub if !self.follows_operand() => Operator::Neg,
+            Operator::Add if !self.follows_operand() => Operator::Pos,
+            operator => operator,
+        };
+        match operator {
+            Operator::LeftParenthesis => self.parentheses.push(self.span(start)),
+            Operator::LeftCurlyBracket | Operator::RightCurlyBracket => {
+                if let Some(&mut (_, ref mut depth)) = self.templates.last_mut() {
+                    if operator == Operator::LeftCurlyBracket { *depth += 1 } else { *depth -= 1 }
+                }
+            }
+            Operator::RightParenthesis if self.parentheses.pop().is_none() => {
+                return Err(Error::UnpairedBrackets.at(self.span(start)));
+            }
+            _ => (),
+        }
+
+        self.push(operator, start);
+        Ok(())
+    }
+
+    fn follows_operand(&self) -> bool {
+        self.tokens.last().map_or(false, |token| token.operator.ends_operand())
+    }
+
+    fn push(&mut self, operator: Operator, start: Mark) {
+        let span = self.span(start);
+        self.tokens.push(Token::new(operator, span));
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.source[self.offset..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.source[self.offset..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let cur = self.peek()?;
+        self.offset += cur.len_utf8();
+        if cur == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(cur)
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn mark(&self) -> Mark {
+        Mark {
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn span(&self, start: Mark) -> Span {
+        Span::new(start.offset, self.offset, start.line, start.column)
+    }
+}
+
+fn is_whitespace(cur: char) -> bool {
+    matches!(cur, ' ' | '\t' | '\n' | '\r')
+}
+
+/// Whether `raw` lexes as a single identifier rather than a keyword or literal.
+pub(crate) fn is_identifier(raw: &str) -> bool {
+    let mut chars = raw.chars();
+    chars.next().map_or(false, is_identifier_start) &&
+        chars.all(is_identifier_continue) &&
+        Operator::from_str(raw).map_or(false, |operator| operator.is_identifier())
+}
+
+pub(crate) fn is_identifier_start(cur: char) -> bool {
+    cur == '_' || is_xid_start(cur)
+}
+
+/// Besides `XID_Continue`, currency symbols such as `€` may appear after the first character, so
+/// keys like `prix_€` stay usable. `$` is always an operator.
+fn is_identifier_continue(cur: char) -> bool {
+    is_xid_continue(cur) || is_currency_symbol(cur)
+}
+
+/// Whether `cur` is a non-ASCII character of the Unicode category `Sc`.
+fn is_currency_symbol(cur: char) -> bool {
+    matches!(cur,
+        '\u{a2}'..='\u{a5}' | '\u{58f}' | '\u{60b}' | '\u{7fe}'..='\u{7ff}' |
+        '\u{9f2}'..='\u{9f3}' | '\u{9fb}' | '\u{af1}' | '\u{bf9}' | '\u{e3f}' | '\u{17db}' |
+        '\u{20a0}'..='\u{20c0}' | '\u{a838}' | '\u{fdfc}' | '\u{fe69}' | '\u{ff04}' |
+        '\u{ffe0}'..='\u{ffe1}' | '\u{ffe5}'..='\u{ffe6}' | '\u{11fdd}'..='\u{11fe0}' |
+        '\u{1e2ff}' | '\u{1ecb0}')
+}
@@ -1,9 +1,12 @@
 //! Eval is a powerful expression evaluator.
 //!
 //! Supported operators: `!` `!=` `""` `''` `()` `[]` `.` `,` `>` `<` `>=` `<=`
-//! `==` `+` `-` `*` `/` `%` `&&` `||` `n..m`.
+//! `==` `+` `-` `*` `/` `%` `**` `//` `&` `|` `^` `<<` `>>` `&&` `||` `??` `?.` `?:` `in`
+//! `not in` `=~` `!~` `n..m` `[a, b]` `{key: value}` `a[-1]` `a[i:j:k]` `a[*].b` `` `${a}` ``,
+//! unary `-`, `+` and `~`.
 //!
-//! Built-in functions: `min()` `max()` `len()` `is_empty()` `array()`.
+//! Built-in functions: `min()` `max()` `sum()` `len()` `is_empty()` `array()` `lower()` `upper()`
+//! `starts_with()` `ends_with()`.
 //!
 //! ## Examples
 //!
@@ -18,6 +21,15 @@
 //! assert_eq!(eval("2 / 2 + 3 / 3"), Ok(to_value(2.0)));
 //! ```
 //!
+//! Number literals may use exponents, `0x`/`0o`/`0b` prefixes and `_` separators:
+//!
+//! ```
+//! use resolver::{eval, to_value};
+//!
+//! assert_eq!(eval("2.5e3 + 0xff"), Ok(to_value(2755.0)));
+//! assert_eq!(eval("1_000_000 / 0b1010"), Ok(to_value(100000.0)));
+//! ```
+//!
 //! You can eval with context:
 //!
 //! ```
@@ -45,6 +57,23 @@
 //!            Ok(to_value(true)));
 //! ```
 //!
+//! Expressions may span several lines, with `#` line comments and `/* */` block comments.
+//! `//` is floor division, so it does not start a comment:
+//!
+//! ```
+//! use resolver::{eval, to_value};
+//!
+//! assert_eq!(eval("7 // 2 # floor division\n\t+ /* one */ 1"), Ok(to_value(4)));
+//! ```
+//!
+//! String literals accept the escapes `\\` `\'` `\"` `\n` `\t` `\r` and `\u{XXXX}`:
+//!
+//! ```
+//! use resolver::{eval, to_value};
+//!
+//! assert_eq!(eval(r"'O\'Brien' + '\u{2764}'"), Ok(to_value("O'Brien❤")));
+//! ```
+//!
 //! You can eval with function:
 //!
 //! ```
@@ -64,6 +93,14 @@
 //! assert_eq!(eval("array(1, 2, 3, 4, 5)"), Ok(to_value(vec![1, 2, 3, 4, 5])));
 //! ```
 //!
+//! Or with array and object literals:
+//!
+//! ```
+//! use resolver::{eval, to_value};
+//!
+//! assert_eq!(eval("[1, 2, {total: 1 + 2}][2].total"), Ok(to_value(3)));
+//! ```
+//!
 //! You can create an integer array with `n..m`:
 //!
 //! ```
@@ -72,6 +109,90 @@
 //! assert_eq!(eval("0..5"), Ok(to_value(vec![0, 1, 2, 3, 4])));
 //! ```
 //!
+//! Errors carry the location of the part of the expression that caused them:
+//!
+//! ```
+//! use resolver::{eval, Error};
+//!
+//! let error = eval("1 + foo()").unwrap_err();
+//! assert_eq!(error.kind(), &Error::FunctionNotExists("foo".to_owned()));
+//! assert_eq!(error.span().map(|span| (span.line, span.column)), Some((1, 5)));
+//!
+//! assert_eq!(error.render("1 + foo()"), "\
+//! error: function `foo` does not exist
+//!  --> line 1, column 5
+//!   |
+//! 1 | 1 + foo()
+//!   |     ^^^^^
+//!   = help: register it with `Expr::function` or `Expr::const_function`
+//! ");
+//! ```
+//!
+//! You can inspect the parsed expression through its syntax tree, see `Visitor` and `VisitorMut`:
+//!
+//! ```
+//! use resolver::{AstKind, BinaryOp, Expr};
+//!
+//! let ast = Expr::new("a + 1").ast().unwrap();
+//! assert!(matches!(ast.kind, AstKind::Binary { op: BinaryOp::Add, .. }));
+//! ```
+//!
+//! Template strings embed expressions with `${...}`. Values other than strings are written as
+//! JSON, so numbers, booleans and null read as in the source:
+//!
+//! ```
+//! use resolver::{Expr, to_value};
+//!
+//! assert_eq!(Expr::new("`Hello ${name}, you have ${len(items)} items`")
+//!                .value("name", "Ann")
+//!                .value("items", vec![1, 2])
+//!                .exec(),
+//!            Ok(to_value("Hello Ann, you have 2 items")));
+//! ```
+//!
+//! `let name = value;` binds a variable for the rest of the expression, shadowing the contexts:
+//!
+//! ```
+//! use resolver::{Expr, to_value};
+//!
+//! assert_eq!(Expr::new("let subtotal = sum(items[*].price); let tax = subtotal * rate; subtotal + tax")
+//!                .value("items", serde_json::json!([{"price": 30}, {"price": 70}]))
+//!                .value("rate", 0.5)
+//!                .exec(),
+//!            Ok(to_value(150.0)));
+//! ```
+//!
+//! A `Template` renders text with `{{ expr }}` placeholders and `{% if %}`/`{% for %}` blocks:
+//!
+//! ```
+//! use resolver::{Template, Context, to_value};
+//!
+//! let template = Template::new("{% for item in items %}{{ item.name }}: {{ item.qty }}\n{% endfor %}").unwrap();
+//! let mut context = Context::new();
+//! context.insert("items".to_owned(), serde_json::json!([{"name": "pen", "qty": 2}]));
+//!
+//! assert_eq!(template.render(&[context]), Ok("pen: 2\n".to_owned()));
+//! ```
+//!
+//! Any function can also be called as a method, where `x.f(a)` means `f(x, a)`:
+//!
+//! ```
+//! use resolver::{Expr, to_value};
+//!
+//! assert_eq!(Expr::new("email.lower().ends_with('@corp.com')")
+//!                .value("email", "Ann@CORP.com")
+//!                .exec(),
+//!            Ok(to_value(true)));
+//! ```
+//!
+//! Expressions can be formatted as canonical source text:
+//!
+//! ```
+//! use resolver::Expr;
+//!
+//! assert_eq!(Expr::new("((a+b))*max( 1,\"c\" )").format(), Ok("(a + b) * max(1, 'c')".to_owned()));
+//! ```
+//!
 //! ## Built-in functions
 //!
 //! ### min()
@@ -80,6 +201,9 @@
 //! ### max()
 //! Accept multiple arguments and return the maximum value.
 //!
+//! ### sum()
+//! Accept multiple arguments and return their sum. Arrays are summed item by item.
+//!
 //! ### len()
 //! Accept single arguments and return the length of value. Only accept String, Array, Object and Null.
 //!
@@ -89,8 +213,14 @@
 //! ### array()
 //! Accept multiple arguments and return an array.
 //!
+//! ### lower(), upper()
+//! Accept a single string and return it in lower or upper case.
+//!
+//! ### starts_with(), ends_with()
+//! Accept two strings and return whether the first starts or ends with the second.
 //!
-#![recursion_limit="100"]
+//!
+#![recursion_limit="256"]
 #![deny(missing_docs)]
 
 #![forbid(unsafe_code)]
@@ -102,11 +232,18 @@ extern crate serde_json;
 mod math;
 mod function;
 mod operator;
-mod node;
+mod lexer;
+mod parser;
 mod tree;
 mod error;
 mod builtin;
 mod expr;
+mod span;
+mod diagnostic;
+mod ast;
+mod format;
+mod pattern;
+mod template;
 
 pub use expr::ExecOptions;
 use function::ConstFunction;
@@ -114,6 +251,10 @@ pub use serde_json::Value;
 pub use error::Error;
 pub use function::Function;
 pub use expr::Expr;
+pub use span::Span;
+pub use diagnostic::Diagnostic;
+pub use template::Template;
+pub use ast::{Ast, AstKind, BinaryOp, UnaryOp, Visitor, VisitorMut, walk, walk_mut};
 
 use std::{collections::HashMap, rc::Rc, cell::RefCell};
 use serde_json::to_value as json_to_value;
@@ -150,6 +291,10 @@ mod tests {
     use crate::tree::Tree;
     use crate::Value;
     use crate::eval;
+    use crate::Span;
+    use crate::Diagnostic;
+    use crate::{Context, Template};
+    use crate::{Ast, AstKind, BinaryOp, UnaryOp, VisitorMut, walk_mut};
 
     #[test]
     fn test_add() {
@@ -183,7 +328,10 @@ mod tests {
 
     #[test]
     fn test_error_brackets_not_with_function() {
-        assert_eq!(eval("5 + ()"), Err(Error::BracketNotWithFunction));
+        assert_eq!(
+            eval("5 + ()"),
+            Err(Error::BracketNotWithFunction.at(Span::new(4, 6, 1, 5)))
+        );
     }
 
     #[test]
@@ -358,6 +506,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_string_escapes() {
+        assert_eq!(
+            Expr::new(r"name == 'O\'Brien'").value("name", "O'Brien").exec(),
+            Ok(to_value(true))
+        );
+        assert_eq!(eval(r#""say \"hi\"""#), Ok(to_value("say \"hi\"")));
+        assert_eq!(eval(r"'a\nb\tc\rd\\e'"), Ok(to_value("a\nb\tc\rd\\e")));
+        assert_eq!(eval(r#"'\"' + "\'""#), Ok(to_value("\"'")));
+        assert_eq!(eval(r"'caf\u{e9} \u{1F600}'"), Ok(to_value("café 😀")));
+    }
+
+    #[test]
+    fn test_error_invalid_escape() {
+        assert_eq!(
+            eval(r"'a\qb'"),
+            Err(Error::InvalidEscape(r"\q".to_owned()).at(Span::new(2, 4, 1, 3)))
+        );
+        assert_eq!(
+            eval(r"'\u{D800}'").unwrap_err().kind(),
+            &Error::InvalidEscape(r"\u{D800}".to_owned())
+        );
+        assert_eq!(
+            eval(r"'\u{1234567}'").unwrap_err().kind(),
+            &Error::InvalidEscape(r"\u{1234567".to_owned())
+        );
+        assert_eq!(
+            eval(r"'\u12'").unwrap_err().kind(),
+            &Error::InvalidEscape(r"\u".to_owned())
+        );
+        assert_eq!(
+            eval(r"'abc\"),
+            Err(Error::UnterminatedString.at(Span::new(4, 5, 1, 5)))
+        );
+    }
+
+    #[test]
+    fn test_unicode_string() {
+        assert_eq!(
+            Expr::new("'café' == name").value("name", "café").exec(),
+            Ok(to_value(true))
+        );
+        assert_eq!(eval("'日本' + \"語\""), Ok(to_value("日本語")));
+    }
+
+    #[test]
+    fn test_unicode_identifier() {
+        let mut données = HashMap::new();
+        données.insert("âge", 42);
+        assert_eq!(
+            Expr::new("prix_€ * 2 > 10 && données.âge == 42 && données['âge'] == 42")
+                .value("prix_€", 6)
+                .value("données", données)
+                .exec(),
+            Ok(to_value(true))
+        );
+        assert_eq!(Expr::new("cost_¥ + prix_£").value("cost_¥", 1).value("prix_£", 2).exec(), Ok(to_value(3)));
+        assert_eq!(eval("a≥1"), Err(Error::UnexpectedCharacter('≥').at(Span::new(1, 4, 1, 2))));
+        assert_eq!(eval("a − 1"), Err(Error::UnexpectedCharacter('−').at(Span::new(2, 5, 1, 3))));
+        assert_eq!(eval("2×3"), Err(Error::UnexpectedCharacter('×').at(Span::new(1, 3, 1, 2))));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(127)), Ok(to_value(1)));
+        assert_eq!(eval(&nested(128)), Err(Error::TooDeep.at(Span::new(128, 129, 1, 129))));
+        assert_eq!(eval(&nested(20000)), Err(Error::TooDeep.at(Span::new(128, 129, 1, 129))));
+        assert_eq!(eval(&format!("1{}", " + 1".repeat(10000))), Err(Error::TooDeep.at(Span::new(510, 511, 1, 511))));
+        assert_eq!(eval(&format!("a{}", ".b".repeat(10000))), Err(Error::TooDeep.at(Span::new(255, 256, 1, 256))));
+        assert_eq!(eval(&format!("a[*]{}", ".b".repeat(10000))).unwrap_err().kind(), &Error::TooDeep);
+
+        // The deepest trees still parse, run and format.
+        let expressions = [
+            format!("{}1", "-".repeat(127)),
+            format!("{}1{}", "[".repeat(127), "]".repeat(127)),
+            format!("{}1", "1 ** ".repeat(127)),
+            format!("{}a{}", "f(".repeat(127), ")".repeat(127)),
+            format!("{}{}", nested(126), " + 1".repeat(127)),
+            format!("a{}", ".b".repeat(127)),
+            format!("a[*]{}", ".b".repeat(127)),
+        ];
+        for expression in expressions.iter() {
+            let mut expr = Expr::new(expression.as_str()).function("f", |values| Ok(values[0].clone()));
+            assert!(expr.exec().is_ok(), "{}", expression);
+            assert!(expr.format().is_ok(), "{}", expression);
+            let deeper = format!("({})", expression);
+            assert_eq!(Expr::new(deeper).exec().unwrap_err().kind(), &Error::TooDeep);
+        }
+    }
+
+    #[test]
+    fn test_unicode_span() {
+        let error = eval("'é' + foo()").unwrap_err();
+        assert_eq!(error.span(), Some(Span::new(7, 12, 1, 7)));
+    }
+
+    #[test]
+    fn test_error_unexpected_character() {
+        assert_eq!(
+            eval("1 @ 2"),
+            Err(Error::UnexpectedCharacter('@').at(Span::new(2, 3, 1, 3)))
+        );
+    }
+
+    #[test]
+    fn test_error_unterminated_string() {
+        assert_eq!(
+            eval("'abc"),
+            Err(Error::UnterminatedString.at(Span::new(0, 4, 1, 1)))
+        );
+    }
+
     #[test]
     fn test_array() {
         assert_eq!(eval("array(1, 2, 3, 4)"), Ok(to_value(vec![1, 2, 3, 4])));
@@ -368,6 +629,47 @@ mod tests {
         assert_eq!(eval("0..5"), Ok(to_value(vec![0, 1, 2, 3, 4])));
     }
 
+    #[test]
+    fn test_error_range() {
+        assert_eq!(
+            eval("1..2..3"),
+            Err(Error::InvalidRange("1..2..3".to_owned()).at(Span::new(0, 7, 1, 1)))
+        );
+        assert_eq!(eval("1..").unwrap_err().kind(), &Error::InvalidRange("1..".to_owned()));
+    }
+
+    #[test]
+    fn test_number_literals() {
+        assert_eq!(eval("1e-5"), Ok(to_value(0.00001)));
+        assert_eq!(eval("2.5E+3"), Ok(to_value(2500.0)));
+        assert_eq!(eval("1e3 - 1"), Ok(to_value(999.0)));
+        assert_eq!(eval("0xff"), Ok(to_value(255)));
+        assert_eq!(eval("0XFF + 0b1010 + 0o17"), Ok(to_value(280)));
+        assert_eq!(eval("1_000_000 * 2"), Ok(to_value(2_000_000)));
+        assert_eq!(eval("0x_ff_ff"), Ok(to_value(65535)));
+        assert_eq!(eval("1_0.0_5"), Ok(to_value(10.05)));
+        assert_eq!(eval("18446744073709551615"), Ok(to_value(u64::MAX)));
+        assert_eq!(eval("18446744073709551616"), Ok(to_value(18446744073709551616.0)));
+        assert_eq!(eval("0..1_0"), Ok(to_value((0..10).collect::<Vec<_>>())));
+    }
+
+    #[test]
+    fn test_error_number_literals() {
+        assert_eq!(
+            eval("1 + 1e"),
+            Err(Error::InvalidNumber("1e".to_owned()).at(Span::new(4, 6, 1, 5)))
+        );
+        for literal in ["1e+", "0xfg", "0x", "0b102", "1_", "1_e5", "1.2.3", "12abc", "1e400",
+                        "0x1_0000_0000_0000_0000"] {
+            assert_eq!(
+                eval(literal).unwrap_err().kind(),
+                &Error::InvalidNumber(literal.trim_end_matches('+').to_owned()),
+                "{}",
+                literal
+            );
+        }
+    }
+
     #[test]
     fn test_range_and_min() {
         assert_eq!(eval("min(0..5)"), Ok(to_value(0)));
@@ -428,6 +730,32 @@ mod tests {
         assert_eq!(eval("3 < 2 || 2 > 1"), Ok(to_value(true)));
     }
 
+    #[test]
+    fn test_short_circuit() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut expr = Expr::new("user != null && user.age > 18 || touch()")
+            .function("touch", move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(to_value(true))
+            });
+        assert_eq!(expr.exec(), Ok(to_value(true)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut user = HashMap::new();
+        user.insert("age", 20);
+        let mut expr = expr.value("user", user);
+        assert_eq!(expr.exec(), Ok(to_value(true)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(eval("false && missing()"), Ok(to_value(false)));
+        assert_eq!(eval("true || 1 / 'a'"), Ok(to_value(true)));
+        assert_eq!(eval("true && missing()").unwrap_err().kind(), &Error::FunctionNotExists("missing".to_owned()));
+    }
+
     #[test]
     fn test_not() {
         assert_eq!(eval("!false"), Ok(to_value(true)));
@@ -442,6 +770,55 @@ mod tests {
         assert_eq!(eval("(!(1 == 2)) == true"), Ok(to_value(true)));
     }
 
+    #[test]
+    fn test_unary_minus() {
+        assert_eq!(eval("-5"), Ok(to_value(-5)));
+        assert_eq!(eval("-2.5"), Ok(to_value(-2.5)));
+        assert_eq!(eval("--5"), Ok(to_value(5)));
+        assert_eq!(eval("3 - -2"), Ok(to_value(5)));
+        assert_eq!(eval("min(-1, -(2 + 3), 4)"), Ok(to_value(-5)));
+        assert_eq!(
+            Expr::new("x * -1 == -(a + b)")
+                .value("x", 7)
+                .value("a", 3)
+                .value("b", 4)
+                .exec(),
+            Ok(to_value(true))
+        );
+    }
+
+    #[test]
+    fn test_unary_plus() {
+        assert_eq!(eval("+5"), Ok(to_value(5)));
+        assert_eq!(eval("2 * +3"), Ok(to_value(6)));
+        assert_eq!(eval("5 + + 5"), Ok(to_value(10)));
+    }
+
+    #[test]
+    fn test_unary_precedence() {
+        assert_eq!(eval("2 * -3 + 1"), Ok(to_value(-5)));
+        assert_eq!(eval("2 + -3 * 4"), Ok(to_value(-10)));
+        assert_eq!(eval("-2 * 3"), Ok(to_value(-6)));
+        assert_eq!(eval("1 - -2 * -3"), Ok(to_value(-5)));
+        assert_eq!(eval("1 + - -2 * 3"), Ok(to_value(7)));
+        assert_eq!(eval("true == !false && 1 > 2"), Ok(to_value(false)));
+
+        let mut object = HashMap::new();
+        object.insert("price", vec![10, 20]);
+        assert_eq!(
+            Expr::new("1 + -object.price[1] * 2").value("object", object).exec(),
+            Ok(to_value(-39))
+        );
+    }
+
+    #[test]
+    fn test_error_unary_minus() {
+        assert_eq!(
+            eval("-'a'"),
+            Err(Error::ExpectedNumber.at(Span::new(0, 4, 1, 1)))
+        );
+    }
+
     #[test]
     fn test_object_access() {
         let mut object = HashMap::new();
@@ -535,27 +912,31 @@ mod tests {
     #[test]
     fn test_error_start_with_non_value_operator() {
         let mut tree = Tree {
-            raw: "+ + 5".to_owned(),
+            raw: "* * 5".to_owned(),
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        tree.parse_operators().unwrap();
+        tree.tokenize().unwrap();
 
-        assert_eq!(tree.parse_node(), Err(Error::StartWithNonValueOperator));
+        assert_eq!(
+            tree.parse_node(),
+            Err(Error::StartWithNonValueOperator.at(Span::new(0, 1, 1, 1)))
+        );
     }
 
     #[test]
     fn test_error_duplicate_operator() {
         let mut tree = Tree {
-            raw: "5 + + 5".to_owned(),
+            raw: "5 + * 5".to_owned(),
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        tree.parse_operators().unwrap();
+        tree.tokenize().unwrap();
 
-        assert_eq!(tree.parse_node(), Err(Error::DuplicateOperatorNode));
+        assert_eq!(
+            tree.parse_node(),
+            Err(Error::DuplicateOperatorNode.at(Span::new(4, 5, 1, 5)))
+        );
     }
 
     #[test]
@@ -565,10 +946,12 @@ mod tests {
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        tree.parse_operators().unwrap();
+        tree.tokenize().unwrap();
 
-        assert_eq!(tree.parse_node(), Err(Error::DuplicateValueNode));
+        assert_eq!(
+            tree.parse_node(),
+            Err(Error::DuplicateValueNode.at(Span::new(6, 7, 1, 7)))
+        );
     }
 
     #[test]
@@ -578,9 +961,10 @@ mod tests {
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-
-        assert_eq!(tree.parse_operators(), Err(Error::UnpairedBrackets));
+        assert_eq!(
+            tree.tokenize(),
+            Err(Error::UnpairedBrackets.at(Span::new(7, 8, 1, 8)))
+        );
     }
 
     #[test]
@@ -590,10 +974,639 @@ mod tests {
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        tree.parse_operators().unwrap();
+        tree.tokenize().unwrap();
+
+        assert_eq!(
+            tree.parse_node(),
+            Err(Error::CommaNotWithFunction.at(Span::new(0, 1, 1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_error_unclosed_brackets() {
+        let error = eval("min(1, (2 + 3)").unwrap_err();
+        assert_eq!(error.kind(), &Error::UnpairedBrackets);
+        assert_eq!(error.span(), Some(Span::new(3, 4, 1, 4)));
+    }
+
+    #[test]
+    fn test_error_exec_span() {
+        let error = eval("'a\nb' + foo()").unwrap_err();
+        assert_eq!(error.kind(), &Error::FunctionNotExists("foo".to_owned()));
+        assert_eq!(error.span(), Some(Span::new(8, 13, 2, 6)));
+        assert_eq!(error.to_string(), "Function not exists: foo (line 2, column 6)");
+    }
+
+    #[test]
+    fn test_diagnostic_render() {
+        let source = "(1 + 2) * ('a' - 1)";
+        assert_eq!(
+            eval(source).unwrap_err().render(source),
+            "error: unsupported operand types\n \
+             --> line 1, column 12\n  \
+             |\n\
+             1 | (1 + 2) * ('a' - 1)\n  \
+             |            ^^^^^^^\n  \
+             = note: left operand is String(\"a\")\n  \
+             = note: right operand is Number(1)\n"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_render_multiline() {
+        let source = "'a\n\tb' + foo()";
+        assert_eq!(
+            eval(source).unwrap_err().render(source),
+            "error: function `foo` does not exist\n \
+             --> line 2, column 7\n  \
+             |\n\
+             2 | \tb' + foo()\n  \
+             | \t     ^^^^^\n  \
+             = help: register it with `Expr::function` or `Expr::const_function`\n"
+        );
+    }
+
+    #[test]
+    fn test_diagnostic_without_span() {
+        let diagnostic = Diagnostic::from(Error::ArgumentsLess(2)).help("pass two numbers");
+        assert_eq!(diagnostic.to_string(), "not enough arguments");
+        assert_eq!(
+            diagnostic.render("pow(2)"),
+            "error: not enough arguments\n\
+             = note: this function needs at least 2 argument(s)\n\
+             = help: pass two numbers\n"
+        );
+
+        // A span that does not fit the source is left out instead of panicking.
+        let diagnostic = Diagnostic::new("bad").span(Span::new(1, 3, 1, 2));
+        assert_eq!(diagnostic.render("é + 1"), "error: bad\n");
+        assert_eq!(diagnostic.render("é"), "error: bad\n");
+        assert_eq!(diagnostic.render("ab + 1").lines().last(), Some("  |  ^^"));
+    }
+
+    #[test]
+    fn test_error_exec_span_innermost() {
+        let error = eval("(1 + 2) * ('a' - 1)").unwrap_err();
+        assert_eq!(error.span(), Some(Span::new(11, 18, 1, 12)));
+    }
+
+    #[test]
+    fn test_precedence_and_associativity() {
+        assert_eq!(eval("1 - 2 - 3"), Ok(to_value(-4)));
+        assert_eq!(eval("10 / 2 * 5"), Ok(to_value(25.0)));
+        assert_eq!(eval("2 * 3 % 4"), Ok(to_value(2)));
+        assert_eq!(eval("1 + 2 * 3 - 4 / 2"), Ok(to_value(5.0)));
+        assert_eq!(eval("1 + 2 == 3 && 4 > 3 || false"), Ok(to_value(true)));
+        assert_eq!(eval("false && true || true"), Ok(to_value(true)));
+        assert_eq!(eval("true || true && false"), Ok(to_value(true)));
+        assert_eq!(eval("!true == false"), Ok(to_value(true)));
+    }
+
+    #[test]
+    fn test_error_unexpected_end() {
+        assert_eq!(eval("1 +"), Err(Error::UnexpectedEnd.at(Span::new(3, 3, 1, 4))));
+        assert_eq!(eval("a."), Err(Error::UnexpectedEnd.at(Span::new(2, 2, 1, 3))));
+        assert_eq!(eval(" "), Err(Error::NoFinalNode.at(Span::new(0, 1, 1, 1))));
+    }
+
+    #[test]
+    fn test_error_parse_spans() {
+        assert_eq!(eval("a.1"), Err(Error::ExpectedIdentifier.at(Span::new(2, 3, 1, 3))));
+        assert_eq!(eval("a[1"), Err(Error::UnpairedBrackets.at(Span::new(1, 2, 1, 2))));
+        assert_eq!(eval("1 ]"), Err(Error::UnpairedBrackets.at(Span::new(2, 3, 1, 3))));
+        assert_eq!(eval("a[]"), Err(Error::BracketNotWithFunction.at(Span::new(1, 3, 1, 2))));
+        assert_eq!(eval("max(1 2)"), Err(Error::DuplicateValueNode.at(Span::new(6, 7, 1, 7))));
+        assert_eq!(eval("(1, 2)"), Err(Error::CommaNotWithFunction.at(Span::new(2, 3, 1, 3))));
+        assert_eq!(eval("1 * (* 2)"), Err(Error::DuplicateOperatorNode.at(Span::new(5, 6, 1, 6))));
+    }
+
+    #[test]
+    fn test_conditional() {
+        assert_eq!(eval("1 > 2 ? 'a' : 'b'"), Ok(to_value("b")));
+        assert_eq!(eval("true ? 1 : false ? 2 : 3"), Ok(to_value(1)));
+        assert_eq!(eval("false ? 1 : false ? 2 : 3"), Ok(to_value(3)));
+        assert_eq!(eval("false || true ? 1 + 1 : 0"), Ok(to_value(2)));
+        assert_eq!(eval("true ? false ? 1 : 2 : 3"), Ok(to_value(2)));
+        assert_eq!(eval("missing ? 1 : -1"), Ok(to_value(-1)));
+        assert_eq!(eval("true ? 1 : missing()"), Ok(to_value(1)));
+        assert_eq!(eval("false ? missing() : 2"), Ok(to_value(2)));
+        assert_eq!(eval("1 ? 2 : 3"), Err(Error::ExpectedBoolean(to_value(1)).at(Span::new(0, 1, 1, 1))));
+        assert_eq!(eval("a ? 1"), Err(Error::ExpectedToken(":".to_owned()).at(Span::new(2, 3, 1, 3))));
+        assert_eq!(eval("a ? 1, 2"), Err(Error::ExpectedToken(":".to_owned()).at(Span::new(5, 6, 1, 6))));
+        assert_eq!(Expr::new("(a ? b : c) ? d : (e ? f : g)").format(), Ok("(a ? b : c) ? d : e ? f : g".to_owned()));
+        assert_eq!(Expr::new("a ? b : c").ast().unwrap().span, Span::new(0, 9, 1, 1));
+    }
+
+    #[test]
+    fn test_coalesce_and_optional_chaining() {
+        let mut object = HashMap::new();
+        object.insert("name", to_value("x"));
+        object.insert("count", to_value(0));
+        object.insert("items", to_value(vec![1, 2]));
+        let exec = |expression: &str| Expr::new(expression).value("object", &object).exec();
+
+        assert_eq!(exec("object.missing ?? 'default'"), Ok(to_value("default")));
+        assert_eq!(exec("object.count ?? missing()"), Ok(to_value(0)));
+        assert_eq!(exec("missing ?? object.missing ?? 3"), Ok(to_value(3)));
+        assert_eq!(exec("missing ?? 1 > 0"), Ok(to_value(true)));
+        assert_eq!(exec("missing ?? false || true"), Ok(to_value(true)));
+        assert_eq!(exec("missing ?? false ? 1 : 2"), Ok(to_value(2)));
+
+        assert_eq!(exec("object.count?.value"), Ok(Value::Null));
+        assert_eq!(exec("object.count?.[0]"), Ok(Value::Null));
+        assert_eq!(exec("object.items?.['a'] ?? 'none'"), Ok(to_value("none")));
+        assert_eq!(exec("object?.items?.[1]"), Ok(to_value(2)));
+        assert_eq!(exec("missing?.a.b"), Ok(Value::Null));
+        assert_eq!(exec("object.count.value"), Err(Error::ExpectedObject.at(Span::new(0, 18, 1, 1))));
+        assert_eq!(exec("object.count?.[missing()]").unwrap_err().kind(), &Error::FunctionNotExists("missing".to_owned()));
+
+        assert_eq!(Expr::new("a ?. b ?.[ c ]??d").format(), Ok("a?.b?.[c] ?? d".to_owned()));
+        assert_eq!(Expr::new("(a ?? b) ?? (c ?? d) || e").format(), Ok("a ?? b ?? (c ?? d) || e".to_owned()));
+    }
+
+    #[test]
+    fn test_in() {
+        let mut user = HashMap::new();
+        user.insert("roles", to_value(vec!["admin", "dev"]));
+        user.insert("in", to_value(true));
+        let exec = |expression: &str| {
+            Expr::new(expression).value("user", &user).value("country", "CA").exec()
+        };
+
+        assert_eq!(exec("country in array('US', 'CA')"), Ok(to_value(true)));
+        assert_eq!(exec("'admin' in user.roles"), Ok(to_value(true)));
+        assert_eq!(exec("'ops' not in user.roles"), Ok(to_value(true)));
+        assert_eq!(exec("'roles' in user && 'email' not in user"), Ok(to_value(true)));
+        assert_eq!(exec("'sub' in 'substring'"), Ok(to_value(true)));
+        assert_eq!(exec("'x' in country"), Ok(to_value(false)));
+        assert_eq!(exec("1 in array(1.0, 2)"), Ok(to_value(true)));
+        assert_eq!(exec("3 in 0..3"), Ok(to_value(false)));
+        assert_eq!(exec("1 in missing"), Ok(to_value(false)));
+        assert_eq!(exec("user.in"), Ok(to_value(true)));
+        assert_eq!(exec("1 + 1 in array(2) == true"), Ok(to_value(true)));
+        assert_eq!(exec("1 in 'abc'"), Err(Error::UnsupportedTypes("Number(1)".to_owned(), "String(\"abc\")".to_owned()).at(Span::new(0, 10, 1, 1))));
+        assert_eq!(Expr::new("a  not   in b").format(), Ok("a not in b".to_owned()));
+        assert_eq!(Expr::new("a not in b").ast().unwrap().span, Span::new(0, 10, 1, 1));
+    }
+
+    #[test]
+    fn test_regex_match() {
+        let exec = |expression: &str| {
+            Expr::new(expression).value("email", "ops@corp.com").value("pattern", "^ops").exec()
+        };
 
-        assert_eq!(tree.parse_node(), Err(Error::CommaNotWithFunction));
+        assert_eq!(exec(r"email =~ '^[^@]+@corp\\.com$'"), Ok(to_value(true)));
+        assert_eq!(exec("email !~ 'example'"), Ok(to_value(true)));
+        assert_eq!(exec("email =~ pattern && 'dev' !~ pattern"), Ok(to_value(true)));
+        assert_eq!(exec("missing =~ 'a'"), Ok(to_value(false)));
+        assert_eq!(exec("1 =~ 'a'").unwrap_err().kind(), &Error::UnsupportedTypes("Number(1)".to_owned(), "String(\"a\")".to_owned()));
+        assert_eq!(exec("'a' =~ 1").unwrap_err().span(), Some(Span::new(0, 8, 1, 1)));
+
+        let error = Expr::new("false && email =~ '('").compile().unwrap_err();
+        assert!(matches!(error.kind(), Error::InvalidRegex(pattern, _) if pattern == "("));
+        assert_eq!(error.span(), Some(Span::new(18, 21, 1, 19)));
+
+        let error = exec("email =~ pattern + '('").unwrap_err();
+        assert!(matches!(error.kind(), Error::InvalidRegex(pattern, _) if pattern == "^ops("));
+        assert_eq!(error.span(), Some(Span::new(0, 22, 1, 1)));
+
+        let mut expr = Expr::new("value =~ pattern").compile().unwrap();
+        for (value, pattern, expected) in [("abc", "b", true), ("abc", "^b", false), ("bcd", "^b", true)].iter() {
+            expr = expr.value("value", value).value("pattern", pattern);
+            assert_eq!(expr.exec(), Ok(to_value(*expected)));
+        }
+    }
+
+    #[test]
+    fn test_pow_and_floor_div() {
+        assert_eq!(eval("2 ** 10"), Ok(to_value(1024)));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(to_value(512)));
+        assert_eq!(eval("-2 ** 2"), Ok(to_value(-4)));
+        assert_eq!(eval("(-2) ** 2"), Ok(to_value(4)));
+        assert_eq!(eval("2 ** -1"), Ok(to_value(0.5)));
+        assert_eq!(eval("4 ** 0.5 * 3"), Ok(to_value(6.0)));
+        assert_eq!(eval("2 ** 64"), Ok(to_value(18446744073709551616.0)));
+        assert_eq!(eval("7 // 2"), Ok(to_value(3)));
+        assert_eq!(eval("-7 // 2"), Ok(to_value(-4)));
+        assert_eq!(eval("7 // -2"), Ok(to_value(-4)));
+        assert_eq!(eval("7.5 // 2"), Ok(to_value(3.0)));
+        assert_eq!(Expr::new("user_id // 100").value("user_id", 12345).exec(), Ok(to_value(123)));
+        assert_eq!(eval("1 // 0"), Err(Error::DivisionByZero.at(Span::new(0, 6, 1, 1))));
+        assert_eq!(eval("'a' ** 2").unwrap_err().kind(), &Error::UnsupportedTypes("String(\"a\")".to_owned(), "Number(2)".to_owned()));
+        assert_eq!(Expr::new("(-2)**2 + 2**(-1) + -(2**2)").format(), Ok("(-2) ** 2 + 2 ** -1 + -2 ** 2".to_owned()));
+    }
+
+    #[test]
+    fn test_bitwise() {
+        assert_eq!(eval("6 & 3"), Ok(to_value(2)));
+        assert_eq!(eval("6 | 3"), Ok(to_value(7)));
+        assert_eq!(eval("6 ^ 3"), Ok(to_value(5)));
+        assert_eq!(eval("~5"), Ok(to_value(-6)));
+        assert_eq!(eval("1 << 4 >> 2"), Ok(to_value(4)));
+        assert_eq!(eval("-16 >> 2"), Ok(to_value(-4)));
+        assert_eq!(eval("1 | 2 ^ 3 & 4"), Ok(to_value(3)));
+        assert_eq!(eval("1 + 1 << 1 + 1"), Ok(to_value(8)));
+        assert_eq!(eval("5 & 4 == 4 && true"), Ok(to_value(true)));
+        assert_eq!(eval("0xff & 0x0f | 0b1 << 8"), Ok(to_value(271)));
+        assert_eq!(eval("18446744073709551615 & 0xff"), Ok(to_value(255u64)));
+        assert_eq!(Expr::new("perms & 4 != 0").value("perms", 6).exec(), Ok(to_value(true)));
+        assert_eq!(eval("1.5 & 1").unwrap_err().kind(), &Error::UnsupportedTypes("Number(1.5)".to_owned(), "Number(1)".to_owned()));
+        assert_eq!(eval("~'a'"), Err(Error::ExpectedInteger.at(Span::new(0, 4, 1, 1))));
+        assert_eq!(eval("1 << 64"), Err(Error::InvalidShift("64".to_owned()).at(Span::new(0, 7, 1, 1))));
+        assert_eq!(eval("1 << -1").unwrap_err().kind(), &Error::InvalidShift("-1".to_owned()));
+        assert_eq!(Expr::new("(a|b)&~c<<(d^e)").format(), Ok("(a | b) & ~c << (d ^ e)".to_owned()));
+    }
+
+    #[test]
+    fn test_collection_literals() {
+        let mut user = HashMap::new();
+        user.insert("name", "Ann");
+        let exec = |expression: &str| Expr::new(expression).value("user", &user).value("x", 3).exec();
+
+        assert_eq!(exec("[1, 2, x]"), Ok(to_value(vec![1, 2, 3])));
+        assert_eq!(exec("[]"), Ok(to_value(Vec::<Value>::new())));
+        assert_eq!(exec("[[1], [x + 1]][1][0]"), Ok(to_value(4)));
+        assert_eq!(exec("len([1, 2] )"), Ok(to_value(2)));
+        assert_eq!(exec("'US' in ['US', 'CA']"), Ok(to_value(true)));
+
+        let mut expected = HashMap::new();
+        expected.insert("name", to_value("Ann"));
+        expected.insert("total", to_value(5));
+        expected.insert("a key", to_value(vec![true]));
+        assert_eq!(exec("{name: user.name, total: x + 2, 'a key': [true]}"), Ok(to_value(&expected)));
+        assert_eq!(exec("{}"), Ok(to_value(HashMap::<String, Value>::new())));
+        assert_eq!(exec("{a: {b: x}}.a.b"), Ok(to_value(3)));
+        assert_eq!(exec("{a: 1, a: 2}['a']"), Ok(to_value(2)));
+
+        assert_eq!(exec("[1, 2"), Err(Error::UnpairedBrackets.at(Span::new(0, 1, 1, 1))));
+        assert_eq!(exec("[1 2]"), Err(Error::DuplicateValueNode.at(Span::new(3, 4, 1, 4))));
+        assert_eq!(exec("{1: 2}"), Err(Error::ExpectedIdentifier.at(Span::new(1, 2, 1, 2))));
+        assert_eq!(exec("{a 2}"), Err(Error::ExpectedToken(":".to_owned()).at(Span::new(3, 4, 1, 4))));
+        assert_eq!(Expr::new("{ 'a': [ 1,2 ], 'b-c': {} }").format(), Ok("{a: [1, 2], 'b-c': {}}".to_owned()));
+        assert_eq!(Expr::new("[1, {a: 2}]").ast().unwrap().span, Span::new(0, 11, 1, 1));
+    }
+
+    #[test]
+    fn test_postfix_chains() {
+        let mut user = HashMap::new();
+        user.insert("name", to_value("user"));
+        user.insert("tags", to_value(vec![vec!["a", "b"], vec!["c"]]));
+        let exec = |expression: &str| {
+            Expr::new(expression)
+                .value("user", &user)
+                .value("rows", vec![&user])
+                .function("me", |_| Ok(to_value(vec![1, 2])))
+                .exec()
+        };
+
+        assert_eq!(exec("array(1, 2)[0]"), Ok(to_value(1)));
+        assert_eq!(exec("me()[1]"), Ok(to_value(2)));
+        assert_eq!(exec("(user).name"), Ok(to_value("user")));
+        assert_eq!(exec("(rows[0]).tags[0][1]"), Ok(to_value("b")));
+        assert_eq!(exec("rows[0].tags[1][0]"), Ok(to_value("c")));
+        assert_eq!(exec("[user][0]['tags'][0][0]"), Ok(to_value("a")));
+        assert_eq!(exec("{u: [user]}.u[0].tags[1]"), Ok(to_value(vec!["c"])));
+        assert_eq!(exec("(rows[0].name == 'user' ? rows : [])[0].name"), Ok(to_value("user")));
+        assert_eq!(exec("max (1, 2) + len([me()][0])"), Ok(to_value(4)));
+
+        // A string value is not an object, even when a context variable has the same name.
+        assert_eq!(exec("user.name.name"), Err(Error::ExpectedObject.at(Span::new(0, 14, 1, 1))));
+        assert_eq!(exec("user.name?.name"), Ok(Value::Null));
+        assert_eq!(exec("(1 + 2)(3)"), Err(Error::NotCallable.at(Span::new(1, 6, 1, 2))));
+        assert_eq!(exec("me()(1)"), Err(Error::NotCallable.at(Span::new(0, 4, 1, 1))));
+        assert_eq!(Expr::new("me ( ) [ 1 ] . a").format(), Ok("me()[1].a".to_owned()));
+        assert_eq!(Expr::new("me(1).a").ast().unwrap().span, Span::new(0, 7, 1, 1));
+    }
+
+    #[test]
+    fn test_negative_index_and_slice() {
+        let exec = |expression: &str| {
+            Expr::new(expression).value("items", vec![0, 1, 2, 3, 4]).value("name", "héllo").exec()
+        };
+
+        assert_eq!(exec("items[-1]"), Ok(to_value(4)));
+        assert_eq!(exec("items[-5]"), Ok(to_value(0)));
+        assert_eq!(exec("items[-6]"), Ok(Value::Null));
+        assert_eq!(exec("items[1:3]"), Ok(to_value(vec![1, 2])));
+        assert_eq!(exec("items[:-1]"), Ok(to_value(vec![0, 1, 2, 3])));
+        assert_eq!(exec("items[-2:]"), Ok(to_value(vec![3, 4])));
+        assert_eq!(exec("items[:2]"), Ok(to_value(vec![0, 1])));
+        assert_eq!(exec("items[::2]"), Ok(to_value(vec![0, 2, 4])));
+        assert_eq!(exec("items[::-1]"), Ok(to_value(vec![4, 3, 2, 1, 0])));
+        assert_eq!(exec("items[3:0:-2]"), Ok(to_value(vec![3, 1])));
+        assert_eq!(exec("items[10:]"), Ok(to_value(Vec::<i64>::new())));
+        assert_eq!(exec("items[-100:2]"), Ok(to_value(vec![0, 1])));
+        assert_eq!(exec("items[:]"), Ok(to_value(vec![0, 1, 2, 3, 4])));
+        assert_eq!(exec("items[missing:1]"), Ok(to_value(vec![0])));
+        assert_eq!(exec("items[true ? 1 : 2:3]"), Ok(to_value(vec![1, 2])));
+
+        assert_eq!(exec("name[0:3]"), Ok(to_value("hél")));
+        assert_eq!(exec("name[1]"), Ok(to_value("é")));
+        assert_eq!(exec("name[-1]"), Ok(to_value("o")));
+        assert_eq!(exec("name[::-1]"), Ok(to_value("olléh")));
+        assert_eq!(exec("name[9]"), Ok(Value::Null));
+        assert_eq!(eval("[0, 1, 2, 3, 4][1::9223372036854775807]"), Ok(to_value(vec![1])));
+        assert_eq!(eval("'abc'[1::9223372036854775807]"), Ok(to_value("b")));
+        assert_eq!(eval("'abc'[1::-9223372036854775807 - 1]"), Ok(to_value("b")));
+
+        assert_eq!(exec("items[::0]"), Err(Error::ZeroStep.at(Span::new(0, 10, 1, 1))));
+        assert_eq!(exec("items[0.5:]").unwrap_err().kind(), &Error::ExpectedInteger);
+        assert_eq!(exec("1[0:1]").unwrap_err().kind(), &Error::ExpectedArray);
+        assert_eq!(exec("1?.[0:1]"), Ok(Value::Null));
+        assert_eq!(Expr::new("a[ 1 : -1 ][:][::2][ : b : ]").format(), Ok("a[1:-1][:][::2][:b]".to_owned()));
+    }
+
+    #[test]
+    fn test_projection() {
+        let orders = serde_json::json!([
+            {"total": 40, "items": [{"sku": "a"}, {"sku": "b"}]},
+            {"total": 120, "items": [{"sku": "c"}]},
+            {"items": []},
+        ]);
+        let exec = |expression: &str| Expr::new(expression).value("orders", &orders).exec();
+
+        assert_eq!(exec("orders[*].total"), Ok(to_value(vec![40, 120])));
+        assert_eq!(exec("max(orders[*].total) > 100"), Ok(to_value(true)));
+        assert_eq!(exec("orders[*].items[*].sku"), Ok(to_value(vec!["a", "b", "c"])));
+        assert_eq!(exec("orders[*].items[0].sku"), Ok(to_value(vec!["a", "c"])));
+        assert_eq!(exec("orders[*].items[-1:]"), Ok(serde_json::json!([[{"sku": "b"}], [{"sku": "c"}], []])));
+        assert_eq!(exec("orders[*]"), Ok(orders.clone()));
+        assert_eq!(exec("missing[*].total"), Ok(Value::Null));
+        assert_eq!(exec("orders[0][*]").unwrap_err().kind(), &Error::ExpectedArray);
+        assert_eq!(exec("orders[0]?.[*]"), Ok(Value::Null));
+        assert_eq!(exec("orders[*].total.x").unwrap_err().span(), Some(Span::new(7, 17, 1, 8)));
+        assert_eq!(Expr::new("a [ * ] . b[ * ]").format(), Ok("a[*].b[*]".to_owned()));
+    }
+
+    #[test]
+    fn test_method_call() {
+        let exec = |expression: &str| {
+            Expr::new(expression)
+                .value("user", serde_json::json!({"email": "Ann@Corp.com", "tags": ["a", "b"], "size": 7}))
+                .function("size", |values| Ok(to_value(values.len())))
+                .function("join", |values| {
+                    let items = values[0].as_array().cloned().unwrap_or_default();
+                    let items = items.iter().filter_map(|item| item.as_str()).collect::<Vec<_>>();
+                    Ok(to_value(items.join(values[1].as_str().unwrap_or(""))))
+                })
+                .exec()
+        };
+
+        assert_eq!(exec("user.email.lower().ends_with('@corp.com')"), Ok(to_value(true)));
+        assert_eq!(exec("user.email.upper() == upper(user.email)"), Ok(to_value(true)));
+        assert_eq!(exec("user.tags.len() + 'abc'.len()"), Ok(to_value(5)));
+        assert_eq!(exec("user.tags.join('-')"), Ok(to_value("a-b")));
+        assert_eq!(exec("[user][*].tags.join('')"), Ok(to_value(vec!["ab"])));
+        // Functions take priority over fields, which are the fallback without arguments.
+        assert_eq!(exec("user.size()"), Ok(to_value(1)));
+        assert_eq!(exec("user.email()"), Ok(to_value("Ann@Corp.com")));
+        assert_eq!(exec("user.missing()"), Err(Error::FunctionNotExists("missing".to_owned()).at(Span::new(0, 14, 1, 1))));
+        assert_eq!(exec("user.lowr()"), Err(Error::FunctionNotExists("lowr".to_owned()).at(Span::new(0, 11, 1, 1))));
+        assert_eq!(exec("'abc'.lowr()"), Err(Error::FunctionNotExists("lowr".to_owned()).at(Span::new(0, 12, 1, 1))));
+        assert_eq!(exec("user.missing.email()").unwrap_err().kind(), &Error::FunctionNotExists("email".to_owned()));
+        assert_eq!(exec("user.missing(1)"), Err(Error::FunctionNotExists("missing".to_owned()).at(Span::new(0, 15, 1, 1))));
+        assert_eq!(exec("user.nothing?.lower()"), Ok(Value::Null));
+        assert_eq!(exec("[user.size?.email, user.size?.[0]]"), Ok(to_value([(), ()])));
+        assert_eq!(exec("user.size?.lower()").unwrap_err().kind(), &Error::Custom("expected a string, found Number(7)".to_owned()));
+        assert_eq!(exec("user.email?.lower()"), Ok(to_value("ann@corp.com")));
+        assert_eq!(exec("user.tags.upper()").unwrap_err().kind(), &Error::Custom("expected a string, found Array [String(\"a\"), String(\"b\")]".to_owned()));
+        assert_eq!(Expr::new("(1).f( a , b ).g ( )").format(), Ok("(1).f(a, b).g()".to_owned()));
+    }
+
+    #[test]
+    fn test_template() {
+        let exec = |expression: &str| {
+            Expr::new(expression).value("user", serde_json::json!({"name": "Ann", "age": 30})).exec()
+        };
+
+        assert_eq!(exec("`Hi ${user.name}, ${user.age + 1}!`"), Ok(to_value("Hi Ann, 31!")));
+        assert_eq!(exec("`${1.5} ${true} ${null} ${missing} ${[1, 'a']} ${{a: 1}}`"),
+                   Ok(to_value("1.5 true null null [1,\"a\"] {\"a\":1}")));
+        assert_eq!(exec("``"), Ok(to_value("")));
+        assert_eq!(exec("`a ${`b ${user.name.upper()}`} c` + '!'"), Ok(to_value("a b ANN c!")));
+        assert_eq!(exec("`\\`\\${x} $x ${'}'} \\n`"), Ok(to_value("`${x} $x } \n")));
+        assert_eq!(exec("`${ {k: 'v'}.k }`"), Ok(to_value("v")));
+
+        assert_eq!(exec("`abc"), Err(Error::UnterminatedString.at(Span::new(0, 4, 1, 1))));
+        assert_eq!(exec("`a ${1 + 2"), Err(Error::UnterminatedString.at(Span::new(0, 10, 1, 1))));
+        assert_eq!(exec("`${1 2}`"), Err(Error::DuplicateValueNode.at(Span::new(5, 6, 1, 6))));
+        assert_eq!(exec("`${1 + 'a'}`").unwrap_err().span(), Some(Span::new(3, 10, 1, 4)));
+        assert_eq!(Expr::new("` ${ a+1 }\\${x} ${ `${b}` }`").format(), Ok("` ${a + 1}\\${x} ${`${b}`}`".to_owned()));
+    }
+
+    #[test]
+    fn test_text_template() {
+        let mut context = Context::new();
+        context.insert("user".to_owned(), serde_json::json!({"name": "Ann", "admin": false}));
+        context.insert("items".to_owned(), serde_json::json!([{"sku": "a", "qty": 2}, {"sku": "b", "qty": 1}]));
+        let render = |source: &str| Template::new(source)?.render(&[context.clone()]);
+
+        assert_eq!(render("Hi {{user.name}}, {{ len(items) }} items"), Ok("Hi Ann, 2 items".to_owned()));
+        assert_eq!(render("{% if user.admin %}admin{% else %}{{ user.name.lower() }}{% endif %}!"),
+                   Ok("ann!".to_owned()));
+        assert_eq!(render("{% if missing %}x{% endif %}"), Ok("".to_owned()));
+        assert_eq!(render("{% for item in items %}[{{ item.sku }}{% if item.qty > 1 %} x{{ item.qty }}{% endif %}]{% endfor %}"),
+                   Ok("[a x2][b]".to_owned()));
+        assert_eq!(render("{% for user in items %}{{ user.sku }}{% endfor %} {{ user.name }}"), Ok("ab Ann".to_owned()));
+        assert_eq!(render("{% for x in missing %}x{% endfor %}{{ null }} {{ 1.5 }} {{ {a: [true]} }}"),
+                   Ok("null 1.5 {\"a\":[true]}".to_owned()));
+        assert_eq!(Template::new("{{ twice(2) }}").unwrap().function("twice", |v| Ok(to_value(v[0].as_u64().unwrap() * 2))).render(&[]),
+                   Ok("4".to_owned()));
+
+        assert_eq!(render("ab {{ 1 + }}"), Err(Error::UnexpectedEnd.at(Span::new(10, 10, 1, 11))));
+        assert_eq!(render("ab\n{{ user.name.x }}"), Err(Error::ExpectedObject.at(Span::new(6, 17, 2, 4))));
+        assert_eq!(render("{% if user.name %}{% endif %}"), Err(Error::ExpectedBoolean(to_value("Ann")).at(Span::new(0, 18, 1, 1))));
+        assert_eq!(render("{% for x in user %}{% endfor %}"), Err(Error::ExpectedArray.at(Span::new(0, 19, 1, 1))));
+        assert_eq!(render("a {{ b"), Err(Error::UnterminatedTag.at(Span::new(2, 6, 1, 3))));
+        assert_eq!(render("{% if a %}"), Err(Error::UnclosedBlock("{% if a %}".to_owned()).at(Span::new(0, 10, 1, 1))));
+        assert_eq!(render("{% endfor %}"), Err(Error::InvalidTag("endfor".to_owned()).at(Span::new(0, 12, 1, 1))));
+        assert_eq!(render("{% for 1 in a %}{% endfor %}"), Err(Error::InvalidTag("for 1 in a".to_owned()).at(Span::new(0, 16, 1, 1))));
+    }
+
+    #[test]
+    fn test_text_template_large_context() {
+        let mut context = Context::new();
+        context.insert("items".to_owned(), to_value((0..200_000).collect::<Vec<_>>()));
+        context.insert("rows".to_owned(), to_value((0..100).collect::<Vec<_>>()));
+        context.insert("x".to_owned(), to_value("outer"));
+        let contexts = [context];
+        let template = Template::new("{% for x in [1, 2] %}{% for y in [x, x * 10] %}{{ x }}:{{ y }} {% endfor %}{% endfor %}{{ x }}").unwrap();
+        assert_eq!(template.render(&contexts), Ok("1:1 1:10 2:2 2:20 outer".to_owned()));
+
+        // Loop variables live in a local scope, so every row still sees the caller's contexts.
+        let template = Template::new("{% for row in rows %}{% for x in [row] %}{{ x }}{% endfor %}{{ x }} {% endfor %}").unwrap();
+        let expected = (0..100).map(|row| format!("{}outer ", row)).collect::<String>();
+        assert_eq!(template.render(&contexts), Ok(expected));
+    }
+
+    #[test]
+    fn test_comments_and_multiline() {
+        let exec = |expression: &str| Expr::new(expression).value("order", serde_json::json!({"total": 120})).exec();
+
+        assert_eq!(exec("order.total > 100 # big orders\n\t&& /* not free */ order.total != 0"), Ok(to_value(true)));
+        assert_eq!(exec("max(\r\n  1,\n  2 /* , 3 */\n)"), Ok(to_value(2)));
+        assert_eq!(exec("# only a comment\n1"), Ok(to_value(1)));
+        assert_eq!(exec("'# not a comment'"), Ok(to_value("# not a comment")));
+        assert_eq!(exec("order.total // 7"), Ok(to_value(17)));
+
+        assert_eq!(exec("1 +\n  /* x */ order.total.x"), Err(Error::ExpectedObject.at(Span::new(14, 27, 2, 11))));
+        assert_eq!(exec("1 /* x"), Err(Error::UnterminatedComment.at(Span::new(2, 6, 1, 3))));
+        assert_eq!(Expr::new("a\n\t+ b # c").format(), Ok("a + b".to_owned()));
+
+        let source = "1 +\n\ttrue";
+        let diagnostic = Diagnostic::from(&eval(source).unwrap_err());
+        assert_eq!(diagnostic.span.map(|span| (span.line, span.column)), Some((1, 1)));
+        let diagnostic = Diagnostic::from(&exec("1 +\n\tfoo(\n)").unwrap_err());
+        assert_eq!(diagnostic.render("1 +\n\tfoo(\n)").lines().nth(3), Some("2 | \tfoo("));
+
+        // `//` is floor division, so comments written that way get a hint.
+        let help = "  = help: `//` is floor division, start comments with `#`";
+        for source in ["order.total > 5 // big orders", "1 //comment", "1 +\n\t2 // x y"].iter() {
+            assert_eq!(exec(source).unwrap_err().render(source).lines().last(), Some(help), "{}", source);
+        }
+        let source = "order.total // 0 1";
+        assert_ne!(exec(source).unwrap_err().render(source).lines().last(), Some(help));
+    }
+
+    #[test]
+    fn test_let_bindings() {
+        let exec = |expression: &str| {
+            Expr::new(expression)
+                .value("items", serde_json::json!([{"price": 30}, {"price": 70}]))
+                .value("x", 10)
+                .value("let", 1)
+                .exec()
+        };
+
+        assert_eq!(exec("let subtotal = sum(items[*].price);\nlet tax = subtotal // 10;\nsubtotal + tax"), Ok(to_value(110)));
+        assert_eq!(exec("let x = x + 1; let x = x * 2; x"), Ok(to_value(22)));
+        assert_eq!(exec("(let x = 1; x) + x"), Ok(to_value(11)));
+        assert_eq!(exec("let n = len(items); n > 1 ? `${n} items` : 'one'"), Ok(to_value("2 items")));
+        assert_eq!(exec("let + 1"), Ok(to_value(2)));
+        assert_eq!(exec("sum(1, [2, 3.5])"), Ok(to_value(6.5)));
+
+        assert_eq!(exec("let y = 1 y"), Err(Error::ExpectedToken(";".to_owned()).at(Span::new(10, 11, 1, 11))));
+        assert_eq!(exec("let y = 1"), Err(Error::ExpectedToken(";".to_owned()).at(Span::new(6, 7, 1, 7))));
+        assert_eq!(exec("let y = 1;"), Err(Error::UnexpectedEnd.at(Span::new(10, 10, 1, 11))));
+        assert_eq!(exec("x = 1"), Err(Error::UnsupportedOperator("=".to_owned()).at(Span::new(2, 3, 1, 3))));
+        assert_eq!(exec("let y = x.a; y").unwrap_err().kind(), &Error::ExpectedObject);
+        assert_eq!(Expr::new("let  a=1 ;\n f(a)").format(), Ok("let a = 1; f(a)".to_owned()));
+
+        // Consecutive statements are not nested, so long programs stay within the nesting limit.
+        let program = (1..500).fold("let v0 = x;".to_owned(), |program, i| format!("{} let v{} = v{} + 1;", program, i, i - 1));
+        assert_eq!(exec(&format!("{} v499 + v0", program)), Ok(to_value(519)));
+        assert_eq!(Expr::new(format!("{} v499", program)).format().unwrap(), format!("{} v499", program));
+    }
+
+    #[test]
+    fn test_let_bindings_large_context() {
+        let mut expr = Expr::new("let a = 1; let b = a + x; let a = a * 10; [a, b, y ?? 0, len(items)]")
+            .value("items", (0..200_000).collect::<Vec<_>>())
+            .value("x", 2)
+            .compile()
+            .unwrap();
+
+        // Bindings live in a local scope, so the contexts are left untouched between executions.
+        assert_eq!(expr.exec(), Ok(to_value([10, 3, 0, 200_000])));
+        assert_eq!(expr.exec(), Ok(to_value([10, 3, 0, 200_000])));
+    }
+
+    #[test]
+    fn test_ast_shape() {
+        let ast = Expr::new("-a.b + f(1)[0]").ast().unwrap();
+        let expected = Expr::new("(-(a.b)) + (f(1)[0])").ast().unwrap();
+        assert_eq!(ast, expected);
+
+        match ast.kind {
+            AstKind::Binary { op: BinaryOp::Add, ref lhs, ref rhs } => {
+                assert_eq!(lhs.span, Span::new(0, 4, 1, 1));
+                assert_eq!(rhs.span, Span::new(7, 14, 1, 8));
+                match lhs.kind {
+                    AstKind::Unary { op: UnaryOp::Neg, ref operand } => {
+                        assert_eq!(operand.kind, AstKind::Member {
+                            object: Box::new(Ast::new(AstKind::Ident("a".to_owned()), Span::new(1, 2, 1, 2))),
+                            name: "b".to_owned(),
+                            optional: false,
+                        });
+                    }
+                    ref kind => panic!("unexpected {:?}", kind),
+                }
+            }
+            ref kind => panic!("unexpected {:?}", kind),
+        }
+        assert_eq!(ast.span, Span::new(0, 14, 1, 1));
+    }
+
+    #[test]
+    fn test_ast_visitor_mut() {
+        struct Rename;
+
+        impl VisitorMut for Rename {
+            fn visit_mut(&mut self, ast: &mut Ast) {
+                if let AstKind::Ident(ref mut name) = ast.kind {
+                    name.make_ascii_uppercase();
+                }
+                walk_mut(self, ast);
+            }
+        }
+
+        let mut ast = Expr::new("x + max(y, z.w)").ast().unwrap();
+        ast.accept_mut(&mut Rename);
+        assert_eq!(ast, Expr::new("X + max(Y, Z.w)").ast().unwrap());
+        assert_eq!(Expr::new("1 +").ast().unwrap_err(), Error::UnexpectedEnd.at(Span::new(3, 3, 1, 4)));
+    }
+
+    #[test]
+    fn test_format() {
+        let format = |expression: &str| Expr::new(expression).format().unwrap();
+        assert_eq!(format("1+2*3"), "1 + 2 * 3");
+        assert_eq!(format("((1 + 2)) * 3"), "(1 + 2) * 3");
+        assert_eq!(format("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(format("(1 - 2) - 3"), "1 - 2 - 3");
+        assert_eq!(format("-(a.b) + !(x)"), "-a.b + !x");
+        assert_eq!(format("-(1 + 2)"), "-(1 + 2)");
+        assert_eq!(format("max( a,b )[0].c"), "max(a, b)[0].c");
+        assert_eq!(format("(a || b) && c"), "(a || b) && c");
+        assert_eq!(format(r#""it's" + '\n\u{1}'"#), r"'it\'s' + '\n\u{1}'");
+        assert_eq!(format("(1).a + (0..3)[1] + 1.5e3"), "(1).a + (0..3)[1] + 1500.0");
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let expressions = [
+            "a + b * (c - d) / e % f",
+            "!(a == b) || c != d && e >= 1_000",
+            "-(-a) + +b - -(c * d)",
+            "max(1, min(2, 3), array(x.y[z], 'q\\\\'))",
+            "(a.b.c[d + 1])['e']",
+            "0x10 * 2.5e-3 - 0..4",
+            "1 - (2 + 3) - (4 - 5)",
+            "(a ? b : c) ? (d ? e : f) : g || h ? i : j",
+            "a?.b?.[c] ?? (d ?? e) || f",
+            "not in a not in b && (c in d) in e",
+            "a =~ 'x\\\\d' != (b !~ c)",
+            "(-a) ** b ** -c // (d ** e) ** f - -g",
+            "~a & (b | c) ^ d << e >> (f & g) | h & i",
+            "!(~a) == ~!b",
+            "[a, [], {b: [c], 'd e': {}, 'in': 1}][0].f",
+            "a[-1][1:][:-b][::c ? 1 : 2]?.[d:e:f]",
+            "a[*].b[*][0]?.c + (1)[*]?.[*]",
+            "a.b(c, d.e()).f?.g()[0] + -(1).h()",
+            "(items[*].price)[0] + (a[*].b).c + (a[*])[*] + (a[*].b).c()",
+            "let a = (let b = 1; b); -(let c = a; c) + f(let d = 2; d) ? let e = 3; e : (let g = 4; g)",
+            "(let a = 1; a).b + let",
+            "`a${b}\\${'c'}\\` ${`${d}`}${e ? {f: 1} : 2}\\n$`.g",
+        ];
+        for expression in expressions.iter() {
+            let ast = Expr::new(*expression).ast().unwrap();
+            let formatted = ast.to_string();
+            assert_eq!(Expr::new(formatted.clone()).ast().unwrap(), ast, "{}", formatted);
+            assert_eq!(Expr::new(formatted.clone()).format().unwrap(), formatted);
+        }
+
+        let items = serde_json::json!([{"price": [5, 6]}, {"price": [7]}]);
+        let expr = Expr::new(Expr::new("(items[*].price)[0]").format().unwrap());
+        assert_eq!(expr.value("items", items).exec(), Ok(to_value([5, 6])));
     }
 
     #[test]
@@ -606,7 +1619,7 @@ mod tests {
     #[test]
     fn test_eval_math_function(){
         fn pow(v: Vec<Value>)->Result<Value, Error>{
-            let Some(base) = v.get(0) else {
+            let Some(base) = v.first() else {
                 return Err(Error::ArgumentsLess(2));
             };
             let Some(pow) = v.get(1) else {
@@ -627,7 +1640,7 @@ mod tests {
             Ok(base.pow(pow as u32).into())
         }
         fn add2(v: Vec<Value>)->Result<Value, Error>{
-            let Some(base) = v.get(0) else {
+            let Some(base) = v.first() else {
                 return Err(Error::ArgumentsLess(1));
             }; 
             let Value::Number(base) = base else {
@@ -656,24 +1669,13 @@ mod benches {
     }
 
     #[bench]
-    fn bench_parse_pos(b: &mut test::Bencher) {
-        let mut tree = Tree {
-            raw: "(2 + (3 + 4) + (6 + (6 + 7)) + 5)".to_owned(),
-            ..Default::default()
-        };
-
-        b.iter(|| tree.parse_pos().unwrap());
-    }
-
-    #[bench]
-    fn bench_parse_operators(b: &mut test::Bencher) {
+    fn bench_tokenize(b: &mut test::Bencher) {
         let mut tree = Tree {
             raw: "(2 + (3 + 4) + (6 + (6 + 7)) + 5)".to_owned(),
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        b.iter(|| tree.parse_operators().unwrap());
+        b.iter(|| tree.tokenize().unwrap());
     }
 
     #[bench]
@@ -683,8 +1685,7 @@ mod benches {
             ..Default::default()
         };
 
-        tree.parse_pos().unwrap();
-        tree.parse_operators().unwrap();
+        tree.tokenize().unwrap();
         b.iter(|| tree.parse_node().unwrap());
     }
 
@@ -695,8 +1696,7 @@ mod benches {
                 raw: "(2 + (3 + 4) + (6 + (6 + 7)) + 5)".to_owned(),
                 ..Default::default()
             };
-            tree.parse_pos().unwrap();
-            tree.parse_operators().unwrap();
+            tree.tokenize().unwrap();
             tree.parse_node().unwrap();
             tree.compile().unwrap();
         });
@@ -1,4 +1,5 @@
 
+use regex::Regex;
 use serde_json::Value;
 use crate::to_value;
 use crate::error::Error;
@@ -9,6 +10,14 @@ pub trait Math {
     fn sub(&self, value: &Value) -> Result<Value, Error>;
     fn div(&self, value: &Value) -> Result<Value, Error>;
     fn rem(&self, value: &Value) -> Result<Value, Error>;
+    fn pow(&self, value: &Value) -> Result<Value, Error>;
+    fn floor_div(&self, value: &Value) -> Result<Value, Error>;
+    fn bit_and(&self, value: &Value) -> Result<Value, Error>;
+    fn bit_or(&self, value: &Value) -> Result<Value, Error>;
+    fn bit_xor(&self, value: &Value) -> Result<Value, Error>;
+    fn shl(&self, value: &Value) -> Result<Value, Error>;
+    fn shr(&self, value: &Value) -> Result<Value, Error>;
+    fn bit_not(&self) -> Result<Value, Error>;
     fn eq(&self, value: &Value) -> Result<Value, Error>;
     fn ne(&self, value: &Value) -> Result<Value, Error>;
     fn gt(&self, value: &Value) -> Result<Value, Error>;
@@ -17,6 +26,12 @@ pub trait Math {
     fn le(&self, value: &Value) -> Result<Value, Error>;
     fn and(&self, value: &Value) -> Result<Value, Error>;
     fn or(&self, value: &Value) -> Result<Value, Error>;
+    fn is_in(&self, value: &Value) -> Result<Value, Error>;
+    fn not_in(&self, value: &Value) -> Result<Value, Error>;
+    fn matches(&self, regex: &Regex) -> Result<Value, Error>;
+    fn not_matches(&self, regex: &Regex) -> Result<Value, Error>;
+    fn neg(&self) -> Result<Value, Error>;
+    fn pos(&self) -> Result<Value, Error>;
 }
 
 impl Math for Value {
@@ -84,6 +99,81 @@ impl Math for Value {
         }
     }
 
+    fn pow(&self, value: &Value) -> Result<Value, Error> {
+        if !self.is_number() || !value.is_number() {
+            return Err(Error::UnsupportedTypes(self.format(), value.format()));
+        }
+
+        // Integer powers stay integral unless they overflow.
+        let exponent = value.as_u64().and_then(|exponent| u32::try_from(exponent).ok());
+        if let Some(exponent) = exponent {
+            if let Some(power) = self.as_i64().and_then(|base| base.checked_pow(exponent)) {
+                return Ok(to_value(power));
+            }
+            if let Some(power) = self.as_u64().and_then(|base| base.checked_pow(exponent)) {
+                return Ok(to_value(power));
+            }
+        }
+        Ok(to_value(self.get_f64().powf(value.get_f64())))
+    }
+
+    fn floor_div(&self, value: &Value) -> Result<Value, Error> {
+        if !self.is_number() || !value.is_number() {
+            return Err(Error::UnsupportedTypes(self.format(), value.format()));
+        }
+        if value.get_f64() == 0.0 {
+            return Err(Error::DivisionByZero);
+        }
+
+        if self.is_i64() && value.is_i64() {
+            let (a, b) = (self.get_i64(), value.get_i64());
+            if let Some(quotient) = a.checked_div(b) {
+                let rounded_down = a % b != 0 && (a < 0) != (b < 0);
+                return Ok(to_value(if rounded_down { quotient - 1 } else { quotient }));
+            }
+        } else if self.is_u64() && value.is_u64() {
+            return Ok(to_value(self.get_u64() / value.get_u64()));
+        }
+        Ok(to_value((self.get_f64() / value.get_f64()).floor()))
+    }
+
+    fn bit_and(&self, value: &Value) -> Result<Value, Error> {
+        bitwise(self, value, |a, b| a & b, |a, b| a & b)
+    }
+
+    fn bit_or(&self, value: &Value) -> Result<Value, Error> {
+        bitwise(self, value, |a, b| a | b, |a, b| a | b)
+    }
+
+    fn bit_xor(&self, value: &Value) -> Result<Value, Error> {
+        bitwise(self, value, |a, b| a ^ b, |a, b| a ^ b)
+    }
+
+    fn shl(&self, value: &Value) -> Result<Value, Error> {
+        let amount = shift_amount(self, value)?;
+        match (self.as_i64(), self.as_u64()) {
+            (Some(a), _) => Ok(to_value(a << amount)),
+            (None, Some(a)) => Ok(to_value(a << amount)),
+            _ => Err(Error::UnsupportedTypes(self.format(), value.format())),
+        }
+    }
+
+    fn shr(&self, value: &Value) -> Result<Value, Error> {
+        let amount = shift_amount(self, value)?;
+        match (self.as_i64(), self.as_u64()) {
+            (Some(a), _) => Ok(to_value(a >> amount)),
+            (None, Some(a)) => Ok(to_value(a >> amount)),
+            _ => Err(Error::UnsupportedTypes(self.format(), value.format())),
+        }
+    }
+
+    fn bit_not(&self) -> Result<Value, Error> {
+        match self.as_i64() {
+            Some(a) => Ok(to_value(!a)),
+            None => Err(Error::ExpectedInteger),
+        }
+    }
+
     fn eq(&self, value: &Value) -> Result<Value, Error> {
         if self.is_number() && value.is_number() {
             Ok(to_value(self.get_f64() == value.get_f64()))
@@ -159,6 +249,86 @@ impl Math for Value {
             Err(Error::UnsupportedTypes(self.format(), value.format()))
         }
     }
+
+    fn is_in(&self, value: &Value) -> Result<Value, Error> {
+        match *value {
+            Value::Null => Ok(to_value(false)),
+            Value::Array(ref array) => {
+                for item in array {
+                    if Math::eq(self, item)? == Value::Bool(true) {
+                        return Ok(to_value(true));
+                    }
+                }
+                Ok(to_value(false))
+            }
+            Value::Object(ref object) if self.is_string() => {
+                Ok(to_value(object.contains_key(self.get_str())))
+            }
+            Value::String(ref string) if self.is_string() => {
+                Ok(to_value(string.contains(self.get_str())))
+            }
+            _ => Err(Error::UnsupportedTypes(self.format(), value.format())),
+        }
+    }
+
+    fn not_in(&self, value: &Value) -> Result<Value, Error> {
+        Ok(to_value(!self.is_in(value)?.get_boolean()))
+    }
+
+    fn matches(&self, regex: &Regex) -> Result<Value, Error> {
+        match *self {
+            Value::String(ref string) => Ok(to_value(regex.is_match(string))),
+            Value::Null => Ok(to_value(false)),
+            _ => Err(Error::UnsupportedTypes(self.format(), to_value(regex.as_str()).format())),
+        }
+    }
+
+    fn not_matches(&self, regex: &Regex) -> Result<Value, Error> {
+        Ok(to_value(!self.matches(regex)?.get_boolean()))
+    }
+
+    fn neg(&self) -> Result<Value, Error> {
+        if self.is_i64() {
+            match self.get_i64().checked_neg() {
+                Some(n) => Ok(to_value(n)),
+                None => Ok(to_value(-self.get_f64())),
+            }
+        } else if self.is_number() {
+            Ok(to_value(-self.get_f64()))
+        } else {
+            Err(Error::ExpectedNumber)
+        }
+    }
+
+    fn pos(&self) -> Result<Value, Error> {
+        if self.is_number() {
+            Ok(self.clone())
+        } else {
+            Err(Error::ExpectedNumber)
+        }
+    }
+}
+
+/// Applies a bitwise operator to two integers, as `u64` only when both are too large for `i64`.
+fn bitwise(a: &Value, b: &Value, signed: fn(i64, i64) -> i64, unsigned: fn(u64, u64) -> u64)
+    -> Result<Value, Error> {
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        Ok(to_value(signed(a, b)))
+    } else if let (Some(a), Some(b)) = (a.as_u64(), b.as_u64()) {
+        Ok(to_value(unsigned(a, b)))
+    } else {
+        Err(Error::UnsupportedTypes(a.format(), b.format()))
+    }
+}
+
+fn shift_amount(a: &Value, b: &Value) -> Result<u32, Error> {
+    if !a.is_i64() && !a.is_u64() || !b.is_i64() && !b.is_u64() {
+        return Err(Error::UnsupportedTypes(a.format(), b.format()));
+    }
+    match b.as_u64() {
+        Some(amount) if amount < 64 => Ok(amount as u32),
+        _ => Err(Error::InvalidShift(b.to_string())),
+    }
 }
 
 
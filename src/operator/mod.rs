@@ -1,39 +1,107 @@
-
 use std::str::FromStr;
 use serde_json::Value;
 use crate::to_value;
 use crate::error::Error;
-use crate::node::Node;
+use crate::ast::{BinaryOp, UnaryOp};
+use crate::span::Span;
 
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operator {
-    Add(u8),
-    Mul(u8),
-    Sub(u8),
-    Div(u8),
-    Rem(u8),
-    Not(u8),
-    Eq(u8),
-    Ne(u8),
-    Gt(u8),
-    Lt(u8),
-    Ge(u8),
-    Le(u8),
-    And(u8),
-    Or(u8),
-    Dot(u8),
+    Add,
+    Mul,
+    Sub,
+    Div,
+    Rem,
+    Pow,
+    FloorDiv,
+    Not,
+    Neg,
+    Pos,
+    BitNot,
+    Eq,
+    Ne,
+    Gt,
+    Lt,
+    Ge,
+    Le,
+    Match,
+    NotMatch,
+    In,
+    NotIn,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Dot,
     LeftParenthesis,
     RightParenthesis,
-    LeftSquareBracket(u8),
+    LeftSquareBracket,
     RightSquareBracket,
-    DoubleQuotes,
-    SingleQuote,
-    WhiteSpace,
+    LeftCurlyBracket,
+    RightCurlyBracket,
     Comma,
-    Function(String),
+    Question,
+    QuestionDot,
+    Coalesce,
+    Colon,
+    Assign,
+    Semicolon,
+    Backtick,
+    DollarLeftCurlyBracket,
     Identifier(String),
     Value(Value),
+    Range(i64, i64),
+}
+
+/// How tightly an operator binds its operands, from loosest to tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    Lowest,
+    Conditional,
+    Coalesce,
+    Or,
+    And,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Sum,
+    Product,
+    Prefix,
+    Power,
+    Postfix,
+}
+
+impl Precedence {
+    pub fn next(self) -> Precedence {
+        match self {
+            Precedence::Lowest => Precedence::Conditional,
+            Precedence::Conditional => Precedence::Coalesce,
+            Precedence::Coalesce => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Comparison,
+            Precedence::Comparison => Precedence::BitOr,
+            Precedence::BitOr => Precedence::BitXor,
+            Precedence::BitXor => Precedence::BitAnd,
+            Precedence::BitAnd => Precedence::Shift,
+            Precedence::Shift => Precedence::Sum,
+            Precedence::Sum => Precedence::Product,
+            Precedence::Product => Precedence::Prefix,
+            Precedence::Prefix => Precedence::Power,
+            Precedence::Power | Precedence::Postfix => Precedence::Postfix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
 }
 
 impl Operator {
@@ -41,125 +109,116 @@ impl Operator {
         matches!(*self, Operator::Identifier(_))
     }
 
-    pub fn can_at_beginning(&self) -> bool {
-        matches!(*self, Operator::Not(_) |
-            Operator::Function(_) |
-            Operator::LeftParenthesis)
-    }
-
-    pub fn get_max_args(&self) -> Option<usize> {
+    pub fn unary_op(&self) -> Option<UnaryOp> {
         match *self {
-            Operator::Add(_) | Operator::Sub(_) | Operator::Mul(_) | Operator::Div(_) |
-            Operator::Eq(_) | Operator::Ne(_) | Operator::Gt(_) | Operator::Lt(_) |
-            Operator::Ge(_) | Operator::Le(_) | Operator::And(_) | Operator::Or(_) |
-            Operator::Rem(_) => Some(2),
-            Operator::Not(_) => Some(1),
-            Operator::Function(_) => None,
-            _ => Some(0),
+            Operator::Not => Some(UnaryOp::Not),
+            Operator::Neg => Some(UnaryOp::Neg),
+            Operator::Pos => Some(UnaryOp::Pos),
+            Operator::BitNot => Some(UnaryOp::BitNot),
+            _ => None,
         }
     }
 
-    pub fn get_min_args(&self) -> Option<usize> {
+    pub fn binary_op(&self) -> Option<BinaryOp> {
         match *self {
-            Operator::Add(_) | Operator::Sub(_) | Operator::Mul(_) | Operator::Div(_) |
-            Operator::Eq(_) | Operator::Ne(_) | Operator::Gt(_) | Operator::Lt(_) |
-            Operator::Ge(_) | Operator::Le(_) | Operator::And(_) | Operator::Or(_) |
-            Operator::Rem(_) => Some(2),
-            Operator::Not(_) => Some(1),
-            Operator::Function(_) => None,
-            _ => Some(0),
+            Operator::Add => Some(BinaryOp::Add),
+            Operator::Sub => Some(BinaryOp::Sub),
+            Operator::Mul => Some(BinaryOp::Mul),
+            Operator::Div => Some(BinaryOp::Div),
+            Operator::Rem => Some(BinaryOp::Rem),
+            Operator::Pow => Some(BinaryOp::Pow),
+            Operator::FloorDiv => Some(BinaryOp::FloorDiv),
+            Operator::Eq => Some(BinaryOp::Eq),
+            Operator::Ne => Some(BinaryOp::Ne),
+            Operator::Gt => Some(BinaryOp::Gt),
+            Operator::Lt => Some(BinaryOp::Lt),
+            Operator::Ge => Some(BinaryOp::Ge),
+            Operator::Le => Some(BinaryOp::Le),
+            Operator::Match => Some(BinaryOp::Match),
+            Operator::NotMatch => Some(BinaryOp::NotMatch),
+            Operator::BitAnd => Some(BinaryOp::BitAnd),
+            Operator::BitOr => Some(BinaryOp::BitOr),
+            Operator::BitXor => Some(BinaryOp::BitXor),
+            Operator::Shl => Some(BinaryOp::Shl),
+            Operator::Shr => Some(BinaryOp::Shr),
+            Operator::In => Some(BinaryOp::In),
+            Operator::NotIn => Some(BinaryOp::NotIn),
+            Operator::And => Some(BinaryOp::And),
+            Operator::Or => Some(BinaryOp::Or),
+            Operator::Coalesce => Some(BinaryOp::Coalesce),
+            _ => None,
         }
     }
 
-    pub fn get_priority(&self) -> u8 {
+    /// Binding of operators written after their left operand.
+    pub fn infix_binding(&self) -> Option<(Precedence, Associativity)> {
         match *self {
-            Operator::Add(priority) |
-            Operator::Sub(priority) |
-            Operator::Div(priority) |
-            Operator::Mul(priority) |
-            Operator::Eq(priority) |
-            Operator::Ne(priority) |
-            Operator::Gt(priority) |
-            Operator::Lt(priority) |
-            Operator::Ge(priority) |
-            Operator::Le(priority) |
-            Operator::And(priority) |
-            Operator::Or(priority) |
-            Operator::Rem(priority) => priority,
-            Operator::Value(_) |
-            Operator::Identifier(_) => 0,
-            _ => 99,
+            Operator::Question => Some((Precedence::Conditional, Associativity::Right)),
+            Operator::Dot | Operator::QuestionDot | Operator::LeftSquareBracket |
+            Operator::LeftParenthesis => {
+                Some((Precedence::Postfix, Associativity::Left))
+            }
+            _ => self.binary_op().map(BinaryOp::binding),
         }
     }
 
-    pub fn is_left_parenthesis(&self) -> bool {
-        *self == Operator::LeftParenthesis
-    }
-
-    pub fn is_not(&self) -> bool {
-        matches!(*self, Operator::Not(_))
-    }
-
-    pub fn is_left_square_bracket(&self) -> bool {
-        matches!(*self, Operator::LeftSquareBracket(_))
-    }
-
-    pub fn is_dot(&self) -> bool {
-        matches!(*self, Operator::Dot(_))
-    }
-
-    pub fn is_value_or_ident(&self) -> bool {
+    /// Whether a `-` or `+` following this operator is binary rather than a sign.
+    pub fn ends_operand(&self) -> bool {
         matches!(*self, Operator::Value(_) |
-            Operator::Identifier(_))
-    }
-
-    pub fn can_have_child(&self) -> bool {
-        matches!(*self, Operator::Function(_) |
-            Operator::Add(_) |
-            Operator::Sub(_) |
-            Operator::Div(_) |
-            Operator::Mul(_) |
-            Operator::Rem(_) |
-            Operator::Eq(_) |
-            Operator::Ne(_) |
-            Operator::Gt(_) |
-            Operator::Lt(_) |
-            Operator::And(_) |
-            Operator::Or(_) |
-            Operator::Ge(_) |
-            Operator::Not(_) |
-            Operator::Dot(_) |
-            Operator::LeftSquareBracket(_) |
-            Operator::Le(_))
-    }
-
-    pub fn is_left(&self) -> bool {
-        matches!(*self, Operator::LeftParenthesis |
-            Operator::LeftSquareBracket(_))
+            Operator::Identifier(_) |
+            Operator::Range(..) |
+            Operator::RightParenthesis |
+            Operator::RightSquareBracket |
+            Operator::RightCurlyBracket |
+            Operator::Backtick)
     }
 
-    pub fn get_left(&self) -> Operator {
+    pub fn get_identifier(&self) -> &str {
         match *self {
-            Operator::RightParenthesis => Operator::LeftParenthesis,
-            Operator::RightSquareBracket => Operator::LeftSquareBracket(100),
-            _ => panic!("not bracket"),
+            Operator::Identifier(ref ident) => ident,
+            _ => panic!("not identifier"),
         }
     }
+}
 
-    pub fn to_node(&self) -> Node {
-        Node::new(self.clone())
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub operator: Operator,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn new(operator: Operator, span: Span) -> Token {
+        Token { operator, span }
     }
+}
 
-    pub fn children_to_node(&self, children: Vec<Node>) -> Node {
-        let mut node = self.to_node();
-        node.children = children;
-        node
+impl UnaryOp {
+    pub(crate) fn binding(self) -> (Precedence, Associativity) {
+        (Precedence::Prefix, Associativity::Right)
     }
+}
 
-    pub fn get_identifier(&self) -> &str {
-        match *self {
-            Operator::Identifier(ref ident) => ident,
-            _ => panic!("not identifier"),
+impl BinaryOp {
+    pub(crate) fn binding(self) -> (Precedence, Associativity) {
+        match self {
+            BinaryOp::Coalesce => (Precedence::Coalesce, Associativity::Left),
+            BinaryOp::Or => (Precedence::Or, Associativity::Left),
+            BinaryOp::And => (Precedence::And, Associativity::Left),
+            BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Gt | BinaryOp::Lt | BinaryOp::Ge |
+            BinaryOp::Le | BinaryOp::Match | BinaryOp::NotMatch | BinaryOp::In |
+            BinaryOp::NotIn => {
+                (Precedence::Comparison, Associativity::Left)
+            }
+            BinaryOp::BitOr => (Precedence::BitOr, Associativity::Left),
+            BinaryOp::BitXor => (Precedence::BitXor, Associativity::Left),
+            BinaryOp::BitAnd => (Precedence::BitAnd, Associativity::Left),
+            BinaryOp::Shl | BinaryOp::Shr => (Precedence::Shift, Associativity::Left),
+            BinaryOp::Add | BinaryOp::Sub => (Precedence::Sum, Associativity::Left),
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem | BinaryOp::FloorDiv => {
+                (Precedence::Product, Associativity::Left)
+            }
+            BinaryOp::Pow => (Precedence::Power, Associativity::Right),
         }
     }
 }
@@ -169,31 +228,47 @@ impl FromStr for Operator {
 
     fn from_str(raw: &str) -> Result<Operator, Error> {
         match raw {
-            "+" => Ok(Operator::Add(8)),
-            "-" => Ok(Operator::Sub(8)),
-            "*" => Ok(Operator::Mul(10)),
-            "/" => Ok(Operator::Div(10)),
-            "%" => Ok(Operator::Rem(10)),
+            "+" => Ok(Operator::Add),
+            "-" => Ok(Operator::Sub),
+            "*" => Ok(Operator::Mul),
+            "/" => Ok(Operator::Div),
+            "%" => Ok(Operator::Rem),
+            "**" => Ok(Operator::Pow),
+            "//" => Ok(Operator::FloorDiv),
             "(" => Ok(Operator::LeftParenthesis),
             ")" => Ok(Operator::RightParenthesis),
-            "[" => Ok(Operator::LeftSquareBracket(100)),
+            "[" => Ok(Operator::LeftSquareBracket),
             "]" => Ok(Operator::RightSquareBracket),
-            "." => Ok(Operator::Dot(100)),
-            "\"" => Ok(Operator::DoubleQuotes),
-            "'" => Ok(Operator::SingleQuote),
-            " " => Ok(Operator::WhiteSpace),
+            "{" => Ok(Operator::LeftCurlyBracket),
+            "}" => Ok(Operator::RightCurlyBracket),
+            "." => Ok(Operator::Dot),
             "," => Ok(Operator::Comma),
-            "!" => Ok(Operator::Not(99)),
+            "?" => Ok(Operator::Question),
+            "?." => Ok(Operator::QuestionDot),
+            "??" => Ok(Operator::Coalesce),
+            ":" => Ok(Operator::Colon),
+            "=" => Ok(Operator::Assign),
+            ";" => Ok(Operator::Semicolon),
+            "!" => Ok(Operator::Not),
+            "~" => Ok(Operator::BitNot),
+            "&" => Ok(Operator::BitAnd),
+            "|" => Ok(Operator::BitOr),
+            "^" => Ok(Operator::BitXor),
+            "<<" => Ok(Operator::Shl),
+            ">>" => Ok(Operator::Shr),
             "false" => Ok(Operator::Value(to_value(false))),
             "true" => Ok(Operator::Value(to_value(true))),
-            "==" => Ok(Operator::Eq(6)),
-            "!=" => Ok(Operator::Ne(6)),
-            ">" => Ok(Operator::Gt(6)),
-            "<" => Ok(Operator::Lt(6)),
-            ">=" => Ok(Operator::Ge(6)),
-            "<=" => Ok(Operator::Le(6)),
-            "&&" => Ok(Operator::And(4)),
-            "||" => Ok(Operator::Or(2)),
+            "==" => Ok(Operator::Eq),
+            "!=" => Ok(Operator::Ne),
+            ">" => Ok(Operator::Gt),
+            "<" => Ok(Operator::Lt),
+            ">=" => Ok(Operator::Ge),
+            "<=" => Ok(Operator::Le),
+            "=~" => Ok(Operator::Match),
+            "!~" => Ok(Operator::NotMatch),
+            "in" => Ok(Operator::In),
+            "&&" => Ok(Operator::And),
+            "||" => Ok(Operator::Or),
             _ => Ok(Operator::Identifier(raw.to_owned())),
         }
     }
@@ -0,0 +1,407 @@
+use crate::ast::{Ast, AstKind};
+use crate::error::Error;
+use crate::operator::{Associativity, Operator, Precedence, Token};
+use crate::span::Span;
+use serde_json::Value;
+
+
+/// Deepest nesting of subexpressions, so hostile input fails cleanly instead of overflowing the
+/// stack.
+pub(crate) const MAX_DEPTH: usize = 128;
+
+/// Precedence-climbing parser turning tokens into an `Ast`. Binding strengths come from
+/// `UnaryOp::binding` and `Operator::infix_binding`.
+pub struct Parser<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    index: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(source: &'a str, tokens: &'a [Token]) -> Parser<'a> {
+        Parser {
+            source,
+            tokens,
+            index: 0,
+            depth: 0,
+        }
+    }
+
+    pub fn parse(mut self) -> Result<Ast, Error> {
+        if self.tokens.is_empty() {
+            return Err(Error::NoFinalNode.at(Span::locate(self.source, 0, self.source.len())));
+        }
+
+        let ast = self.expression(Precedence::Lowest)?;
+        match self.next() {
+            Some(token) => Err(unexpected(token)),
+            None => Ok(ast),
+        }
+    }
+
+    fn expression(&mut self, min: Precedence) -> Result<Ast, Error> {
+        if self.depth == MAX_DEPTH {
+            let span = self.peek().map_or_else(|| self.end(), |token| token.span);
+            return Err(Error::TooDeep.at(span));
+        }
+
+        self.depth += 1;
+        let ast = self.climb(min);
+        self.depth -= 1;
+        ast
+    }
+
+    fn climb(&mut self, min: Precedence) -> Result<Ast, Error> {
+        let mut lhs = self.prefix()?;
+        // Operators in the loop wrap `lhs` without recursing, so count the levels they add.
+        let mut depth = self.depth;
+
+        while let Some(token) = self.peek() {
+            let binding = match token.operator.infix_binding() {
+                Some(binding) if binding.0 >= min => binding,
+                _ => break,
+            };
+            self.index += 1;
+            depth = wrap(depth, token)?;
+
+            lhs = match token.operator.binary_op() {
+                Some(op) => {
+                    let rhs = self.expression(operand_precedence(binding))?;
+                    let span = lhs.span.to(rhs.span);
+                    Ast::new(AstKind::Binary { op, lhs: Box::new(lhs), rhs: Box::new(rhs) }, span)
+                }
+                None if token.operator == Operator::Question => self.conditional(lhs, token)?,
+                None => self.postfix(lhs, token)?,
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    /// Applies the postfix operator `token` (`.`, `?.`, `[` or `(`) to `lhs`.
+    fn postfix(&mut self, lhs: Ast, token: &'a Token) -> Result<Ast, Error> {
+        match token.operator {
+            Operator::Dot => self.member(lhs, false),
+            Operator::QuestionDot => match self.eat(&Operator::LeftSquareBracket) {
+                Some(open) => self.index_access(lhs, open, true),
+                None => self.member(lhs, true),
+            },
+            Operator::LeftParenthesis => self.call(lhs, token),
+            _ => self.index_access(lhs, token, false),
+        }
+    }
+
+    /// Parses `[*]` and the postfix operators after it, which apply to every item.
+    fn projection(&mut self, object: Ast, open: &Token, optional: bool) -> Result<Ast, Error> {
+        let star = self.next().unwrap();
+        let close = self.expect(&Operator::RightSquareBracket, open)?;
+        let mut rest = Ast::new(AstKind::Current, star.span);
+        let mut depth = self.depth;
+
+        while let Some(token) = self.peek() {
+            match token.operator.infix_binding() {
+                Some((Precedence::Postfix, _)) => self.index += 1,
+                _ => break,
+            }
+            depth = wrap(depth, token)?;
+            rest = self.postfix(rest, token)?;
+        }
+
+        let span = object.span.to(close.span).to(rest.span);
+        let kind = AstKind::Projection { object: Box::new(object), rest: Box::new(rest), optional };
+        Ok(Ast::new(kind, span))
+    }
+
+    fn prefix(&mut self) -> Result<Ast, Error> {
+        let token = match self.next() {
+            Some(token) => token,
+            None => return Err(Error::UnexpectedEnd.at(self.end())),
+        };
+
+        if let Some(op) = token.operator.unary_op() {
+            let operand = self.expression(operand_precedence(op.binding()))?;
+            let span = token.span.to(operand.span);
+            return Ok(Ast::new(AstKind::Unary { op, operand: Box::new(operand) }, span));
+        }
+
+        match token.operator {
+            Operator::Value(ref value) => Ok(Ast::new(AstKind::Literal(value.clone()), token.span)),
+            Operator::Identifier(_) if self.starts_binding(self.index - 1) => self.binding(token),
+            Operator::Identifier(ref ident) => Ok(Ast::new(AstKind::Ident(ident.clone()), token.span)),
+            Operator::Range(start, end) => Ok(Ast::new(AstKind::Range { start, end }, token.span)),
+            Operator::LeftSquareBracket => self.array(token),
+            Operator::LeftCurlyBracket => self.object(token),
+            Operator::Backtick => self.template(token),
+            Operator::LeftParenthesis => {
+                if let Some(close) = self.eat(&Operator::RightParenthesis) {
+                    return Err(Error::BracketNotWithFunction.at(token.span.to(close.span)));
+                }
+
+                let ast = self.expression(Precedence::Lowest)?;
+                self.expect(&Operator::RightParenthesis, token)?;
+                Ok(ast)
+            }
+            Operator::Comma => Err(Error::CommaNotWithFunction.at(token.span)),
+            Operator::Assign => Err(unexpected(token)),
+            _ if self.index == 1 => Err(Error::StartWithNonValueOperator.at(token.span)),
+            _ => Err(Error::DuplicateOperatorNode.at(token.span)),
+        }
+    }
+
+    fn call(&mut self, callee: Ast, open: &Token) -> Result<Ast, Error> {
+        let span = callee.span;
+        let mut args = Vec::new();
+        let close = self.delimited(open, &Operator::RightParenthesis, |parser| {
+            args.push(parser.expression(Precedence::Lowest)?);
+            Ok(())
+        })?;
+        let kind = match callee.kind {
+            AstKind::Member { object, name, optional } => AstKind::Method { object, name, args, optional },
+            AstKind::Ident(name) => AstKind::Call { name, args },
+            _ => return Err(Error::NotCallable.at(span)),
+        };
+        Ok(Ast::new(kind, span.to(close.span)))
+    }
+
+    fn template(&mut self, open: &Token) -> Result<Ast, Error> {
+        let mut strings = Vec::new();
+        let mut values = Vec::new();
+
+        // The lexer alternates text with placeholders and ends with the closing backtick.
+        loop {
+            match self.next() {
+                Some(&Token { operator: Operator::Value(Value::String(ref text)), .. }) => {
+                    strings.push(text.clone())
+                }
+                _ => return Err(Error::UnterminatedString.at(open.span)),
+            }
+            match self.next() {
+                Some(close) if close.operator == Operator::Backtick => {
+                    let span = open.span.to(close.span);
+                    return Ok(Ast::new(AstKind::Template { strings, values }, span));
+                }
+                Some(placeholder) => {
+                    values.push(self.expression(Precedence::Lowest)?);
+                    self.expect(&Operator::RightCurlyBracket, placeholder)?;
+                }
+                None => return Err(Error::UnterminatedString.at(open.span)),
+            }
+        }
+    }
+
+    fn array(&mut self, open: &Token) -> Result<Ast, Error> {
+        let mut items = Vec::new();
+        let close = self.delimited(open, &Operator::RightSquareBracket, |parser| {
+            items.push(parser.expression(Precedence::Lowest)?);
+            Ok(())
+        })?;
+        Ok(Ast::new(AstKind::Array(items), open.span.to(close.span)))
+    }
+
+    fn object(&mut self, open: &Token) -> Result<Ast, Error> {
+        let mut entries = Vec::new();
+        let close = self.delimited(open, &Operator::RightCurlyBracket, |parser| {
+            let key = match parser.next() {
+                Some(token) if token.operator.is_identifier() => {
+                    token.operator.get_identifier().to_owned()
+                }
+                Some(&Token { operator: Operator::Value(Value::String(ref key)), .. }) => key.clone(),
+                Some(token) => return Err(Error::ExpectedIdentifier.at(token.span)),
+                None => return Err(Error::UnpairedBrackets.at(open.span)),
+            };
+            match parser.next() {
+                Some(token) if token.operator == Operator::Colon => (),
+                Some(token) => return Err(Error::ExpectedToken(":".to_owned()).at(token.span)),
+                None => return Err(Error::UnpairedBrackets.at(open.span)),
+            }
+            entries.push((key, parser.expression(Precedence::Lowest)?));
+            Ok(())
+        })?;
+        Ok(Ast::new(AstKind::Object(entries), open.span.to(close.span)))
+    }
+
+    /// Parses comma-separated items up to the `close` bracket matching `open`.
+    fn delimited<F>(&mut self, open: &Token, close: &Operator, mut item: F)
+        -> Result<&'a Token, Error>
+        where F: FnMut(&mut Parser<'a>) -> Result<(), Error>
+    {
+        if let Some(token) = self.eat(close) {
+            return Ok(token);
+        }
+
+        loop {
+            item(self)?;
+
+            match self.next() {
+                Some(token) if token.operator == Operator::Comma => (),
+                Some(token) if token.operator == *close => return Ok(token),
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(Error::UnpairedBrackets.at(open.span)),
+            }
+        }
+    }
+
+    /// Whether the tokens at `index` read `let name =`; elsewhere `let` is an ordinary name.
+    fn starts_binding(&self, index: usize) -> bool {
+        let operator = |offset: usize| self.tokens.get(index + offset).map(|token| &token.operator);
+        matches!(operator(0), Some(Operator::Identifier(ident)) if ident == "let") &&
+            operator(1).map_or(false, Operator::is_identifier) &&
+            operator(2) == Some(&Operator::Assign)
+    }
+
+    /// Parses consecutive `let` statements into one node, so a long program is not nested.
+    fn binding(&mut self, keyword: &Token) -> Result<Ast, Error> {
+        let mut bindings = Vec::new();
+        loop {
+            let name = self.next().unwrap().operator.get_identifier().to_owned();
+            let assign = self.next().unwrap();
+            let value = self.expression(Precedence::Lowest)?;
+            match self.next() {
+                Some(token) if token.operator == Operator::Semicolon => (),
+                Some(token) => return Err(Error::ExpectedToken(";".to_owned()).at(token.span)),
+                None => return Err(Error::ExpectedToken(";".to_owned()).at(assign.span)),
+            }
+            bindings.push((name, value));
+
+            if !self.starts_binding(self.index) {
+                break;
+            }
+            self.index += 1;
+        }
+
+        let body = self.expression(Precedence::Lowest)?;
+        let span = keyword.span.to(body.span);
+        Ok(Ast::new(AstKind::Let { bindings, body: Box::new(body) }, span))
+    }
+
+    fn conditional(&mut self, condition: Ast, question: &Token) -> Result<Ast, Error> {
+        let then = self.expression(Precedence::Lowest)?;
+        match self.next() {
+            Some(token) if token.operator == Operator::Colon => (),
+            Some(token) => return Err(Error::ExpectedToken(":".to_owned()).at(token.span)),
+            None => return Err(Error::ExpectedToken(":".to_owned()).at(question.span)),
+        }
+
+        let otherwise = self.expression(Precedence::Conditional)?;
+        let span = condition.span.to(otherwise.span);
+        Ok(Ast::new(AstKind::Conditional {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        }, span))
+    }
+
+    fn member(&mut self, object: Ast, optional: bool) -> Result<Ast, Error> {
+        match self.next() {
+            Some(token) if token.operator.is_identifier() => {
+                let name = token.operator.get_identifier().to_owned();
+                let span = object.span.to(token.span);
+                Ok(Ast::new(AstKind::Member { object: Box::new(object), name, optional }, span))
+            }
+            Some(token) => Err(Error::ExpectedIdentifier.at(token.span)),
+            None => Err(Error::UnexpectedEnd.at(self.end())),
+        }
+    }
+
+    fn index_access(&mut self, object: Ast, open: &Token, optional: bool) -> Result<Ast, Error> {
+        if let Some(close) = self.eat(&Operator::RightSquareBracket) {
+            return Err(Error::BracketNotWithFunction.at(open.span.to(close.span)));
+        }
+
+        if self.peek().map_or(false, |token| token.operator == Operator::Mul) &&
+            self.tokens.get(self.index + 1).map_or(false, |token| {
+                token.operator == Operator::RightSquareBracket
+            }) {
+            return self.projection(object, open, optional);
+        }
+
+        let start = self.slice_bound()?;
+        if self.eat(&Operator::Colon).is_none() {
+            let index = start.unwrap();
+            let close = self.expect(&Operator::RightSquareBracket, open)?;
+            let span = object.span.to(close.span);
+            return Ok(Ast::new(AstKind::Index { object: Box::new(object), index, optional }, span));
+        }
+
+        let end = self.slice_bound()?;
+        let step = match self.eat(&Operator::Colon) {
+            Some(_) => self.slice_bound()?,
+            None => None,
+        };
+        let close = self.expect(&Operator::RightSquareBracket, open)?;
+        let span = object.span.to(close.span);
+        let kind = AstKind::Slice { object: Box::new(object), start, end, step, optional };
+        Ok(Ast::new(kind, span))
+    }
+
+    /// Parses a slice bound, which is omitted before `:` or `]`.
+    fn slice_bound(&mut self) -> Result<Option<Box<Ast>>, Error> {
+        match self.peek() {
+            Some(token) if token.operator == Operator::Colon ||
+                token.operator == Operator::RightSquareBracket => Ok(None),
+            _ => Ok(Some(Box::new(self.expression(Precedence::Lowest)?))),
+        }
+    }
+
+    /// Consumes the closing bracket of `open`.
+    fn expect(&mut self, close: &Operator, open: &Token) -> Result<&'a Token, Error> {
+        match self.next() {
+            Some(token) if token.operator == *close => Ok(token),
+            Some(token) => Err(unexpected(token)),
+            None => Err(Error::UnpairedBrackets.at(open.span)),
+        }
+    }
+
+    fn eat(&mut self, operator: &Operator) -> Option<&'a Token> {
+        match self.peek() {
+            Some(token) if token.operator == *operator => self.next(),
+            _ => None,
+        }
+    }
+
+    /// Empty span after the last character.
+    fn end(&self) -> Span {
+        Span::locate(self.source, self.source.len(), self.source.len())
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.index);
+        if token.is_some() {
+            self.index += 1;
+        }
+        token
+    }
+}
+
+/// Minimum precedence of the operand on the right of an operator.
+fn operand_precedence((precedence, associativity): (Precedence, Associativity)) -> Precedence {
+    match associativity {
+        Associativity::Left => precedence.next(),
+        Associativity::Right => precedence,
+    }
+}
+
+/// Depth of the node `token` wraps around a subtree at `depth`.
+fn wrap(depth: usize, token: &Token) -> Result<usize, Error> {
+    if depth == MAX_DEPTH {
+        return Err(Error::TooDeep.at(token.span));
+    }
+    Ok(depth + 1)
+}
+
+/// Error for a token found where an operator or the end of the expression was expected.
+fn unexpected(token: &Token) -> Error {
+    match token.operator {
+        Operator::Assign => Error::UnsupportedOperator("=".to_owned()).at(token.span),
+        Operator::Comma => Error::CommaNotWithFunction.at(token.span),
+        Operator::RightParenthesis | Operator::RightSquareBracket | Operator::RightCurlyBracket => {
+            Error::UnpairedBrackets.at(token.span)
+        }
+        _ => Error::DuplicateValueNode.at(token.span),
+    }
+}
@@ -0,0 +1,72 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::ast::{Ast, AstKind, BinaryOp, Visitor, walk};
+use crate::error::Error;
+
+
+/// Dynamic patterns cached beyond this count are compiled on every use.
+const CAPACITY: usize = 128;
+
+/// Regular expressions used by `=~` and `!~`, compiled once per pattern.
+#[derive(Default)]
+pub struct Patterns {
+    cache: RefCell<HashMap<String, Regex>>,
+}
+
+impl Patterns {
+    /// Compile the literal patterns of `ast` up front, so invalid ones fail at compile time.
+    pub fn compile(ast: &Ast) -> Result<Patterns, Error> {
+        let mut literals = Literals { patterns: HashMap::new(), error: None };
+        ast.accept(&mut literals);
+
+        match literals.error {
+            Some(error) => Err(error),
+            None => Ok(Patterns { cache: RefCell::new(literals.patterns) }),
+        }
+    }
+
+    /// Compiled `pattern`, from the cache when possible.
+    pub fn get(&self, pattern: &str) -> Result<Regex, Error> {
+        if let Some(regex) = self.cache.borrow().get(pattern) {
+            return Ok(regex.clone());
+        }
+
+        let regex = compile(pattern)?;
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() < CAPACITY {
+            cache.insert(pattern.to_owned(), regex.clone());
+        }
+        Ok(regex)
+    }
+}
+
+struct Literals {
+    patterns: HashMap<String, Regex>,
+    error: Option<Error>,
+}
+
+impl Visitor for Literals {
+    fn visit(&mut self, ast: &Ast) {
+        if let AstKind::Binary { op: BinaryOp::Match, ref rhs, .. } |
+            AstKind::Binary { op: BinaryOp::NotMatch, ref rhs, .. } = ast.kind {
+            if let AstKind::Literal(Value::String(ref pattern)) = rhs.kind {
+                match compile(pattern) {
+                    Ok(regex) => {
+                        self.patterns.insert(pattern.clone(), regex);
+                    }
+                    Err(error) if self.error.is_none() => self.error = Some(error.at(rhs.span)),
+                    Err(_) => (),
+                }
+            }
+        }
+        walk(self, ast);
+    }
+}
+
+fn compile(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|error| Error::InvalidRegex(pattern.to_owned(), error.to_string()))
+}
@@ -0,0 +1,61 @@
+use std::fmt;
+
+
+/// Location of a piece of the source expression.
+///
+/// `start` and `end` are byte offsets, `line` and `column` point at `start` and count from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// Byte offset of the first character.
+    pub start: usize,
+    /// Byte offset after the last character.
+    pub end: usize,
+    /// Line of the first character.
+    pub line: usize,
+    /// Column of the first character, counted in characters.
+    pub column: usize,
+}
+
+impl Span {
+    /// Create a span.
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Create the span of `source[start..end]`, computing its line and column.
+    pub fn locate(source: &str, start: usize, end: usize) -> Span {
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|index| index + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        Span::new(start, end, line, column)
+    }
+
+    /// Return the smallest span covering both spans.
+    pub fn to(self, other: Span) -> Span {
+        let mut span = if other.start < self.start { other } else { self };
+        span.end = self.end.max(other.end);
+        span
+    }
+
+    /// Length of the span in bytes.
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the span covers no characters.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
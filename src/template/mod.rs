@@ -0,0 +1,258 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::Error;
+use crate::function::{ConstFunction, StaticFunction};
+use crate::lexer::is_identifier;
+use crate::span::Span;
+use crate::tree::{stringify, Scope, ScopedCompiled, Tree};
+use crate::{ConstFunctions, Context, Function, Functions, Value};
+
+
+/// Text template with `{{ expr }}` placeholders and `{% if %}`/`{% for %}` blocks.
+///
+/// Blocks are written `{% if expr %}...{% else %}...{% endif %}` and
+/// `{% for name in expr %}...{% endfor %}`. A tag ends at the first `}}` or `%}`. Every
+/// expression is compiled when the template is created, and errors are located in the template
+/// source.
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+    functions: Functions,
+    const_functions: Rc<RefCell<ConstFunctions>>,
+}
+
+enum Node {
+    Text(String),
+    Value(Placeholder),
+    If {
+        condition: Placeholder,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    For {
+        name: String,
+        items: Placeholder,
+        body: Vec<Node>,
+    },
+}
+
+/// Compiled expression of a tag.
+struct Placeholder {
+    compiled: ScopedCompiled,
+    /// Offset of the expression in the template source.
+    offset: usize,
+    /// The whole tag.
+    span: Span,
+}
+
+impl Template {
+    /// Parse a template and compile its expressions.
+    pub fn new<T: Into<String>>(source: T) -> Result<Template, Error> {
+        let source = source.into();
+        let nodes = Parser { source: &source, offset: 0 }.parse()?;
+
+        Ok(Template {
+            source,
+            nodes,
+            functions: Functions::new(),
+            const_functions: Rc::from(RefCell::from(ConstFunctions::new())),
+        })
+    }
+
+    /// Set function. Have highest priority.
+    pub fn function<T, F>(mut self, name: T, function: F) -> Template
+        where T: Into<String>,
+              F: 'static + Fn(Vec<Value>) -> Result<Value, Error> + Sync + Send
+    {
+        self.functions.insert(name.into(), Function::new(function));
+        self
+    }
+
+    /// Set const function. Have lowest priority.
+    pub fn const_function<T>(self, name: T, function: StaticFunction) -> Template
+        where T: Into<String>
+    {
+        self.const_functions.borrow_mut().insert(name.into(), ConstFunction::new(function));
+        self
+    }
+
+    /// Render the template. The value of the last context is searched first.
+    pub fn render(&self, contexts: &[Context]) -> Result<String, Error> {
+        let mut output = String::new();
+        self.render_nodes(&self.nodes, contexts, &Scope::Empty, &mut output)?;
+        Ok(output)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], contexts: &[Context], scope: &Scope, output: &mut String)
+        -> Result<(), Error> {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => output.push_str(text),
+                Node::Value(ref placeholder) => {
+                    output.push_str(&stringify(&self.exec(placeholder, contexts, scope)?));
+                }
+                Node::If { ref condition, ref then, ref otherwise } => {
+                    let branch = match self.exec(condition, contexts, scope)? {
+                        Value::Bool(true) => then,
+                        Value::Bool(false) | Value::Null => otherwise,
+                        value => return Err(Error::ExpectedBoolean(value).at(condition.span)),
+                    };
+                    self.render_nodes(branch, contexts, scope, output)?;
+                }
+                Node::For { ref name, ref items, ref body } => {
+                    let values = match self.exec(items, contexts, scope)? {
+                        Value::Array(values) => values,
+                        Value::Null => continue,
+                        _ => return Err(Error::ExpectedArray.at(items.span)),
+                    };
+
+                    for value in values {
+                        let bindings = [(name.as_str(), value)];
+                        let scope = Scope::Local { bindings: &bindings, parent: scope };
+                        self.render_nodes(body, contexts, &scope, output)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn exec(&self, placeholder: &Placeholder, contexts: &[Context], scope: &Scope)
+        -> Result<Value, Error> {
+        (placeholder.compiled)(contexts, scope, &self.functions, Rc::clone(&self.const_functions))
+            .map_err(|error| relocate(error, &self.source, placeholder.offset, placeholder.span))
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+/// Piece of template source.
+enum Item<'a> {
+    Text(&'a str),
+    /// Content of `{{ }}`, starting at `offset`.
+    Expression(&'a str, usize, Span),
+    /// Content of `{% %}`, starting at `offset`.
+    Statement(&'a str, usize, Span),
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<Vec<Node>, Error> {
+        self.block(None, &[]).map(|(nodes, _)| nodes)
+    }
+
+    /// Nodes up to one of the `ends` statements, which is returned; `open` is the tag that
+    /// started the block, if any.
+    fn block(&mut self, open: Option<Span>, ends: &[&str]) -> Result<(Vec<Node>, &'a str), Error> {
+        let mut nodes = Vec::new();
+
+        loop {
+            let (content, offset, span) = match self.next()? {
+                None => match open {
+                    Some(span) => {
+                        let tag = self.source[span.start..span.end].to_owned();
+                        return Err(Error::UnclosedBlock(tag).at(span));
+                    }
+                    None => return Ok((nodes, "")),
+                },
+                Some(Item::Text(text)) => {
+                    nodes.push(Node::Text(text.to_owned()));
+                    continue;
+                }
+                Some(Item::Expression(content, offset, span)) => {
+                    nodes.push(Node::Value(self.compile(content, offset, span)?));
+                    continue;
+                }
+                Some(Item::Statement(content, offset, span)) => (content, offset, span),
+            };
+
+            let trimmed = content.trim_start();
+            let keyword = trimmed.split(char::is_whitespace).next().unwrap_or("");
+            let rest = &trimmed[keyword.len()..];
+            let rest_offset = offset + content.len() - rest.len();
+
+            match keyword {
+                _ if ends.contains(&keyword) && rest.trim().is_empty() => return Ok((nodes, keyword)),
+                "if" => {
+                    let condition = self.compile(rest, rest_offset, span)?;
+                    let (then, end) = self.block(Some(span), &["else", "endif"])?;
+                    let otherwise = match end {
+                        "else" => self.block(Some(span), &["endif"])?.0,
+                        _ => Vec::new(),
+                    };
+                    nodes.push(Node::If { condition, then, otherwise });
+                }
+                "for" => {
+                    let binding = rest.trim_start();
+                    let name = binding.split(char::is_whitespace).next().unwrap_or("");
+                    let items = binding[name.len()..].trim_start();
+                    if !is_identifier(name) || !items.starts_with("in") ||
+                        !items[2..].starts_with(char::is_whitespace) {
+                        return Err(Error::InvalidTag(content.trim().to_owned()).at(span));
+                    }
+
+                    let items = &items[2..];
+                    let items = self.compile(items, offset + content.len() - items.len(), span)?;
+                    let body = self.block(Some(span), &["endfor"])?.0;
+                    nodes.push(Node::For { name: name.to_owned(), items, body });
+                }
+                _ => return Err(Error::InvalidTag(content.trim().to_owned()).at(span)),
+            }
+        }
+    }
+
+    fn next(&mut self) -> Result<Option<Item<'a>>, Error> {
+        let source = self.source;
+        let rest = &source[self.offset..];
+        if rest.is_empty() {
+            return Ok(None);
+        }
+
+        let open = match (rest.find("{{"), rest.find("{%")) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => rest.len(),
+        };
+        if open > 0 {
+            self.offset += open;
+            return Ok(Some(Item::Text(&rest[..open])));
+        }
+
+        let start = self.offset;
+        let close = if rest.starts_with("{{") { "}}" } else { "%}" };
+        let end = match rest[2..].find(close) {
+            Some(index) => start + 2 + index,
+            None => return Err(Error::UnterminatedTag.at(Span::locate(source, start, source.len()))),
+        };
+        self.offset = end + 2;
+
+        let content = &source[start + 2..end];
+        let span = Span::locate(source, start, self.offset);
+        if close == "}}" {
+            Ok(Some(Item::Expression(content, start + 2, span)))
+        } else {
+            Ok(Some(Item::Statement(content, start + 2, span)))
+        }
+    }
+
+    fn compile(&self, expression: &str, offset: usize, span: Span) -> Result<Placeholder, Error> {
+        match Tree::new(expression).compile_scoped() {
+            Ok(compiled) => Ok(Placeholder { compiled, offset, span }),
+            Err(error) => Err(relocate(error, self.source, offset, span)),
+        }
+    }
+}
+
+/// Moves the span of an error in the expression at `offset` into the template source, or onto
+/// the whole `tag` when it has none.
+fn relocate(error: Error, source: &str, offset: usize, tag: Span) -> Error {
+    match error {
+        Error::At(error, span) => {
+            Error::At(error, Span::locate(source, offset + span.start, offset + span.end))
+        }
+        error => error.at(tag),
+    }
+}
@@ -1,23 +1,25 @@
+use crate::ast::{Ast, AstKind, BinaryOp, UnaryOp};
 use crate::builtin::BuiltIn;
 use crate::error::Error;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::pattern::Patterns;
 use crate::math::Math;
-use crate::node::Node;
-use crate::operator::Operator;
+use crate::operator::Token;
 use crate::Compiled;
 use crate::{to_value, ConstFunctions};
-use crate::{Context, Functions};
-use serde_json::Value;
+use crate::{Context, Function, Functions};
+use regex::Regex;
+use serde_json::{Map, Value};
 use std::cell::RefCell;
 use std::clone::Clone;
 use std::rc::Rc;
-use std::str::FromStr;
 
 #[derive(Default)]
 pub struct Tree {
     pub raw: String,
-    pub pos: Vec<usize>,
-    pub operators: Vec<Operator>,
-    pub node: Option<Node>,
+    pub tokens: Vec<Token>,
+    pub ast: Option<Ast>,
 }
 
 impl Tree {
@@ -28,430 +30,277 @@ impl Tree {
         }
     }
 
-    pub fn parse_pos(&mut self) -> Result<(), Error> {
-        let mut found_quote = false;
-        let mut pos = Vec::new();
-
-        for (index, cur) in self.raw.chars().enumerate() {
-            match cur {
-                '(' | ')' | '+' | '-' | '*' | '/' | ',' | ' ' | '!' | '=' | '>' | '<' | '\''
-                | '[' | ']' | '.' | '%' | '&' | '|' => {
-                    if !found_quote {
-                        pos.push(index);
-                        pos.push(index + 1);
-                    }
-                }
-                '"' => {
-                    found_quote = !found_quote;
-                    pos.push(index);
-                    pos.push(index + 1);
-                }
-                _ => (),
-            }
-        }
-
-        pos.push(self.raw.len());
-
-        self.pos = pos;
+    pub fn tokenize(&mut self) -> Result<(), Error> {
+        self.tokens = Lexer::new(&self.raw).tokenize()?;
         Ok(())
     }
 
-    pub fn parse_operators(&mut self) -> Result<(), Error> {
-        let mut operators = Vec::new();
-        let mut start;
-        let mut end = 0;
-        let mut parenthesis = 0;
-        let mut quote = None;
-        let mut prev = String::new();
-        let mut number = String::new();
-
-        for pos_ref in &self.pos {
-            let pos = *pos_ref;
-            if pos == 0 {
-                continue;
-            } else {
-                start = end;
-                end = pos;
-            }
-
-            let raw = self.raw[start..end].to_owned();
-
-            if raw.is_empty() {
-                continue;
-            }
-
-            let operator = Operator::from_str(&raw).unwrap();
-            match operator {
-                Operator::DoubleQuotes | Operator::SingleQuote => {
-                    if quote.is_some() {
-                        if quote.as_ref() == Some(&operator) {
-                            operators.push(Operator::Value(to_value(&prev)));
-                            prev.clear();
-                            quote = None;
-                            continue;
-                        }
-                    } else {
-                        quote = Some(operator);
-                        prev.clear();
-                        continue;
-                    }
-                }
-                _ => (),
-            };
-
-            if quote.is_some() {
-                prev += &raw;
-                continue;
-            }
-
-            if parse_number(&raw).is_some() || operator.is_dot() {
-                number += &raw;
-                continue;
-            } else if !number.is_empty() {
-                operators.push(Operator::from_str(&number).unwrap());
-                number.clear();
-            }
-
-            if raw == "=" {
-                if prev == "!" || prev == ">" || prev == "<" || prev == "=" {
-                    prev.push('=');
-                    operators.push(Operator::from_str(&prev).unwrap());
-                    prev.clear();
-                } else {
-                    prev = raw;
-                }
-                continue;
-            } else if raw == "!" || raw == ">" || raw == "<" {
-                if prev == "!" || prev == ">" || prev == "<" {
-                    operators.push(Operator::from_str(&prev).unwrap());
-                    prev.clear();
-                } else {
-                    prev = raw;
-                }
-                continue;
-            } else if prev == "!" || prev == ">" || prev == "<" {
-                operators.push(Operator::from_str(&prev).unwrap());
-                prev.clear();
-            }
-
-            if (raw == "&" || raw == "|") && (prev == "&" || prev == "|") {
-                if raw == prev {
-                    prev.push_str(&raw);
-                    operators.push(Operator::from_str(&prev).unwrap());
-                    prev.clear();
-                    continue;
-                } else {
-                    return Err(Error::UnsupportedOperator(prev));
-                }
-            } else if raw == "&" || raw == "|" {
-                prev = raw;
-                continue;
-            }
-
-            match operator {
-                Operator::LeftParenthesis => {
-                    parenthesis += 1;
-
-                    if !operators.is_empty() {
-                        let prev_operator = operators.pop().unwrap();
-                        if prev_operator.is_identifier() {
-                            operators.push(Operator::Function(
-                                prev_operator.get_identifier().to_owned(),
-                            ));
-                            operators.push(operator);
-                            continue;
-                        } else {
-                            operators.push(prev_operator);
-                        }
-                    }
-                }
-                Operator::RightParenthesis => parenthesis -= 1,
-                Operator::WhiteSpace => continue,
-                _ => (),
-            }
-
-            prev = raw;
-            operators.push(operator);
-        }
-
-        if !number.is_empty() {
-            operators.push(Operator::from_str(&number).unwrap());
-        }
-
-        if parenthesis != 0 {
-            Err(Error::UnpairedBrackets)
-        } else {
-            self.operators = operators;
-            Ok(())
-        }
-    }
-
     pub fn parse_node(&mut self) -> Result<(), Error> {
-        let mut parsing_nodes = Vec::<Node>::new();
-
-        for operator in &self.operators {
-            match *operator {
-                Operator::Add(priority)
-                | Operator::Sub(priority)
-                | Operator::Mul(priority)
-                | Operator::Div(priority)
-                | Operator::Not(priority)
-                | Operator::Eq(priority)
-                | Operator::Ne(priority)
-                | Operator::Gt(priority)
-                | Operator::Lt(priority)
-                | Operator::Ge(priority)
-                | Operator::And(priority)
-                | Operator::Or(priority)
-                | Operator::Le(priority)
-                | Operator::Dot(priority)
-                | Operator::LeftSquareBracket(priority)
-                | Operator::Rem(priority) => {
-                    if !parsing_nodes.is_empty() {
-                        let prev = parsing_nodes.pop().unwrap();
-                        if prev.is_value_or_full_children() {
-                            if prev.operator.get_priority() < priority && !prev.closed {
-                                parsing_nodes.extend_from_slice(&rob_to(prev, operator.to_node()));
-                            } else {
-                                parsing_nodes.push(operator.children_to_node(vec![prev]));
-                            }
-                        } else if prev.operator.can_at_beginning() {
-                            parsing_nodes.push(prev);
-                            parsing_nodes.push(operator.to_node());
-                        } else {
-                            return Err(Error::DuplicateOperatorNode);
-                        }
-                    } else if operator.can_at_beginning() {
-                        parsing_nodes.push(operator.to_node());
-                    } else {
-                        return Err(Error::StartWithNonValueOperator);
-                    }
-                }
-                Operator::Function(_) | Operator::LeftParenthesis => {
-                    parsing_nodes.push(operator.to_node())
-                }
-                Operator::Comma => close_comma(&mut parsing_nodes)?,
-                Operator::RightParenthesis | Operator::RightSquareBracket => {
-                    close_bracket(&mut parsing_nodes, operator.get_left())?
-                }
-                Operator::Value(_) | Operator::Identifier(_) => {
-                    append_value_to_last_node(&mut parsing_nodes, operator)?
-                }
-                _ => (),
-            }
-        }
-
-        self.node = Some(get_final_node(parsing_nodes)?);
+        self.ast = Some(Parser::new(&self.raw, &self.tokens).parse()?);
         Ok(())
     }
 
-    pub fn compile(mut self) -> Result<Compiled, Error> {
-        self.parse_pos()?;
-        self.parse_operators()?;
+    pub fn parse(mut self) -> Result<Ast, Error> {
+        self.tokenize()?;
         self.parse_node()?;
-        let node = self.node.unwrap();
+        Ok(self.ast.unwrap())
+    }
+
+    pub fn compile(self) -> Result<Compiled, Error> {
+        let compiled = self.compile_scoped()?;
+        Ok(Box::new(move |contexts, functions, const_functions| {
+            compiled(contexts, &Scope::Empty, functions, const_functions)
+        }))
+    }
+
+    /// Like `compile`, with local variables that are looked up before the contexts.
+    pub(crate) fn compile_scoped(self) -> Result<ScopedCompiled, Error> {
+        let ast = self.parse()?;
+        let patterns = Patterns::compile(&ast)?;
         let builtin = BuiltIn::create_builtins();
 
         Ok(Box::new(
-            move |contexts, functions, const_functions| -> Result<Value, Error> {
-                return exec_node(&node, &builtin, contexts, functions, const_functions);
+            move |contexts, scope, functions, const_functions| -> Result<Value, Error> {
+                return exec_node(&ast, &builtin, &patterns, contexts, scope, functions, const_functions);
 
-            #[rustfmt::skip]
-            fn exec_node(node: &Node,
+            fn exec_node(ast: &Ast,
                          builtin: &Functions,
+                         patterns: &Patterns,
                          contexts: &[Context],
+                         scope: &Scope,
                          functions: &Functions,
                          const_functions: Rc<RefCell<ConstFunctions>>,)
                          -> Result<Value, Error> {
-                match node.operator {
-                    Operator::Add(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .add(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Mul(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .mul(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Sub(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .sub(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Div(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .div(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Rem(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .rem(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Eq(_) => {
-                        Math::eq(&exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))?,
-                                 &exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Ne(_) => {
-                        Math::ne(&exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))?,
-                                 &exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Gt(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .gt(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
-                    }
-                    Operator::Lt(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .lt(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
+                exec_ast(ast, builtin, patterns, contexts, scope, functions, const_functions)
+                    .map_err(|error| error.at(ast.span))
+            }
+
+            #[rustfmt::skip]
+            fn exec_ast(ast: &Ast,
+                        builtin: &Functions,
+                        patterns: &Patterns,
+                        contexts: &[Context],
+                        scope: &Scope,
+                        functions: &Functions,
+                        const_functions: Rc<RefCell<ConstFunctions>>,)
+                        -> Result<Value, Error> {
+                match ast.kind {
+                    AstKind::Binary { op, ref lhs, ref rhs } => {
+                        let lhs = exec_node(lhs, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        match (op, lhs.as_bool()) {
+                            (BinaryOp::And, Some(false)) | (BinaryOp::Or, Some(true)) => return Ok(lhs),
+                            (BinaryOp::Coalesce, _) if !lhs.is_null() => return Ok(lhs),
+                            _ => (),
+                        }
+                        let rhs = exec_node(rhs, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        match op {
+                            BinaryOp::Add => lhs.add(&rhs),
+                            BinaryOp::Sub => lhs.sub(&rhs),
+                            BinaryOp::Mul => lhs.mul(&rhs),
+                            BinaryOp::Div => lhs.div(&rhs),
+                            BinaryOp::Rem => lhs.rem(&rhs),
+                            BinaryOp::Pow => lhs.pow(&rhs),
+                            BinaryOp::FloorDiv => lhs.floor_div(&rhs),
+                            BinaryOp::Eq => Math::eq(&lhs, &rhs),
+                            BinaryOp::Ne => Math::ne(&lhs, &rhs),
+                            BinaryOp::Gt => lhs.gt(&rhs),
+                            BinaryOp::Lt => lhs.lt(&rhs),
+                            BinaryOp::Ge => lhs.ge(&rhs),
+                            BinaryOp::Le => lhs.le(&rhs),
+                            BinaryOp::Match => lhs.matches(&regex(patterns, &lhs, &rhs)?),
+                            BinaryOp::NotMatch => lhs.not_matches(&regex(patterns, &lhs, &rhs)?),
+                            BinaryOp::BitAnd => lhs.bit_and(&rhs),
+                            BinaryOp::BitOr => lhs.bit_or(&rhs),
+                            BinaryOp::BitXor => lhs.bit_xor(&rhs),
+                            BinaryOp::Shl => lhs.shl(&rhs),
+                            BinaryOp::Shr => lhs.shr(&rhs),
+                            BinaryOp::In => lhs.is_in(&rhs),
+                            BinaryOp::NotIn => lhs.not_in(&rhs),
+                            BinaryOp::And => lhs.and(&rhs),
+                            BinaryOp::Or => lhs.or(&rhs),
+                            BinaryOp::Coalesce => Ok(rhs),
+                        }
                     }
-                    Operator::Ge(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .ge(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
+                    AstKind::Unary { op, ref operand } => {
+                        let value = exec_node(operand, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        match op {
+                            UnaryOp::Neg => value.neg(),
+                            UnaryOp::Pos => value.pos(),
+                            UnaryOp::BitNot => value.bit_not(),
+                            UnaryOp::Not => match value {
+                                Value::Bool(boolean) => Ok(Value::Bool(!boolean)),
+                                Value::Null => Ok(Value::Bool(true)),
+                                _ => Err(Error::ExpectedBoolean(value)),
+                            },
+                        }
                     }
-                    Operator::Le(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .le(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
+                    AstKind::Let { ref bindings, ref body } => {
+                        let mut values = Vec::with_capacity(bindings.len());
+                        for (name, value) in bindings {
+                            let local = Scope::Local { bindings: &values, parent: scope };
+                            let value = exec_node(value, builtin, patterns, contexts, &local, functions, Rc::clone(&const_functions))?;
+                            values.push((name.as_str(), value));
+                        }
+                        let local = Scope::Local { bindings: &values, parent: scope };
+                        exec_node(body, builtin, patterns, contexts, &local, functions, const_functions)
                     }
-                    Operator::And(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .and(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
+                    AstKind::Conditional { ref condition, ref then, ref otherwise } => {
+                        let value = exec_node(condition, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        let branch = match value {
+                            Value::Bool(true) => then,
+                            Value::Bool(false) | Value::Null => otherwise,
+                            _ => return Err(Error::ExpectedBoolean(value).at(condition.span)),
+                        };
+                        exec_node(branch, builtin, patterns, contexts, scope, functions, const_functions)
                     }
-                    Operator::Or(_) => {
-                        exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))
-                            ?
-                            .or(&exec_node(&node.get_last_child(), builtin, contexts, functions, Rc::clone(&const_functions))?)
+                    AstKind::Call { ref name, ref args } => {
+                        let mut values = Vec::new();
+                        for arg in args {
+                            values.push(exec_node(arg, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?);
+                        }
+                        call(name, values, builtin, functions, &const_functions)
                     }
-                    Operator::Function(ref ident) => {
-                        let function_option = if functions.contains_key(ident) {
-                            functions.get(ident)
-                        } else {
-                            builtin.get(ident)
-                        };
+                    AstKind::Literal(ref value) => Ok(value.clone()),
+                    AstKind::Array(ref items) => {
                         let mut values = Vec::new();
-                        for node in &node.children {
-                            values.push(exec_node(node, builtin, contexts, functions, Rc::clone(&const_functions))?);
+                        for item in items {
+                            values.push(exec_node(item, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?);
                         }
-
-                        if let Some(fo) = function_option {
-                            let function = fo;
-                            node.check_function_args(function)?;
-                            (function.compiled)(values)
-                        } else if let Some(f) = const_functions.borrow().get(ident){
-                            (f.compiled)(values)
-                        } else {
-                            Err(Error::FunctionNotExists(ident.to_owned()))
+                        Ok(Value::Array(values))
+                    }
+                    AstKind::Object(ref entries) => {
+                        let mut object = Map::new();
+                        for (key, value) in entries {
+                            object.insert(key.clone(), exec_node(value, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?);
                         }
+                        Ok(Value::Object(object))
+                    }
+                    AstKind::Member { ref object, .. } |
+                    AstKind::Index { ref object, .. } |
+                    AstKind::Slice { ref object, .. } |
+                    AstKind::Method { ref object, .. } |
+                    AstKind::Projection { ref object, .. } => {
+                        let object = exec_node(object, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        postfix(ast, object, builtin, patterns, contexts, scope, functions, const_functions)
                     }
-                    Operator::Value(ref value) => Ok(value.clone()),
-                    Operator::Not(_) => {
-                        let value =
-                            exec_node(&node.get_first_child(), builtin, contexts, functions, Rc::clone(&const_functions))?;
-                        match value {
-                            Value::Bool(boolean) => Ok(Value::Bool(!boolean)),
-                            Value::Null => Ok(Value::Bool(true)),
-                            _ => Err(Error::ExpectedBoolean(value)),
+                    AstKind::Current => Ok(Value::Null),
+                    AstKind::Template { ref strings, ref values } => {
+                        let mut text = strings[0].clone();
+                        for (value, string) in values.iter().zip(&strings[1..]) {
+                            let value = exec_node(value, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                            text += &stringify(&value);
+                            text += string;
                         }
+                        Ok(Value::String(text))
                     }
-                    Operator::Dot(_) => {
-                        let mut value = None;
-                        for child in &node.children {
-                            if value.is_none() {
-                                let name = exec_node(child, builtin, contexts, functions, Rc::clone(&const_functions))?;
-                                if name.is_string() {
-                                    value = find(contexts, name.as_str().unwrap());
-                                    if value.is_none() {
-                                        return Ok(Value::Null);
-                                    }
-                                } else if name.is_object() {
-                                    value = Some(name);
-                                } else if name.is_null() {
-                                    return Ok(Value::Null);
-                                } else {
-                                    return Err(Error::ExpectedObject);
-                                }
-                            } else if child.operator.is_identifier() {
-                                value = value.as_ref()
-                                    .unwrap()
-                                    .get(child.operator.get_identifier())
-                                    .cloned();
-                            } else {
-                                return Err(Error::ExpectedIdentifier);
-                            }
+                    AstKind::Range { start, end } => Ok(to_value((start..end).collect::<Vec<_>>())),
+                    AstKind::Ident(ref ident) => {
+                        match scope.get(ident).cloned().or_else(|| find(contexts, ident)) {
+                            Some(value) => Ok(value),
+                            None => Ok(Value::Null),
                         }
+                    }
+                }
+            }
 
-                        if let Some(v) = value {
-                            Ok(v)
-                        } else {
-                            Ok(Value::Null)
+            /// Applies the postfix operator `ast` to its evaluated `object`.
+            #[rustfmt::skip]
+            #[allow(clippy::too_many_arguments)]
+            fn postfix(ast: &Ast,
+                       object: Value,
+                       builtin: &Functions,
+                       patterns: &Patterns,
+                       contexts: &[Context],
+                       scope: &Scope,
+                       functions: &Functions,
+                       const_functions: Rc<RefCell<ConstFunctions>>,)
+                       -> Result<Value, Error> {
+                let (result, optional) = match ast.kind {
+                    AstKind::Member { ref name, optional, .. } => (member(&object, name), optional),
+                    AstKind::Index { ref index, optional, .. } => {
+                        let index = exec_node(index, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        (index_access(&object, &index), optional)
+                    }
+                    AstKind::Slice { ref start, ref end, ref step, optional, .. } => {
+                        let mut bounds = Vec::new();
+                        for bound in [start, end, step].iter() {
+                            bounds.push(match **bound {
+                                Some(ref bound) => exec_node(bound, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?,
+                                None => Value::Null,
+                            });
                         }
+                        (slice(&object, &bounds[0], &bounds[1], &bounds[2]), optional)
                     }
-                    Operator::LeftSquareBracket(_) => {
-                        let mut value = None;
-                        for child in &node.children {
-                            let name = exec_node(child, builtin, contexts, functions, Rc::clone(&const_functions))?;
-                            if value.is_none() {
-                                if name.is_string() {
-                                    value = find(contexts, name.as_str().unwrap());
-                                    if value.is_none() {
-                                        return Ok(Value::Null);
-                                    }
-                                } else if name.is_array() || name.is_object(){
-                                    value = Some(name);
-                                } else if name.is_null() {
-                                    return Ok(Value::Null);
-                                } else {
-                                    return Err(Error::ExpectedArray);
-                                }
-                            } else if value.as_ref().unwrap().is_object() {
-                                if name.is_string() {
-                                    value = value.as_ref()
-                                        .unwrap()
-                                        .get(name.as_str().unwrap())
-                                        .cloned();
-                                } else {
-                                    return Err(Error::ExpectedIdentifier);
-                                }
-                            } else if name.is_u64() {
-                                if value.as_ref().unwrap().is_array() {
-                                    value = value.as_ref()
-                                        .unwrap()
-                                        .as_array()
-                                        .unwrap()
-                                        .get(name.as_u64().unwrap() as usize)
-                                        .cloned();
-                                } else {
-                                    return Err(Error::ExpectedArray);
-                                }
-                            } else {
-                                return Err(Error::ExpectedNumber);
+                    AstKind::Method { ref name, ref args, optional, .. } => {
+                        if optional && object.is_null() {
+                            return Ok(Value::Null);
+                        }
+                        // Without a function of that name, `object.name()` reads an existing field.
+                        let field = match object {
+                            Value::Object(ref fields) if args.is_empty() && !has_function(name, builtin, functions, &const_functions) => {
+                                fields.get(name).cloned()
                             }
+                            _ => None,
+                        };
+                        if let Some(field) = field {
+                            return Ok(field);
                         }
-                        if let Some(v) = value {
-                            Ok(v)
-                        } else {
-                            Ok(Value::Null)
+                        let mut values = vec![object];
+                        for arg in args {
+                            values.push(exec_node(arg, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?);
                         }
+                        (call(name, values, builtin, functions, &const_functions), false)
                     }
-                    Operator::Identifier(ref ident) => {
-                        let number = parse_number(ident);
-                        if let Some(n) = number {
-                            Ok(n)
-                        } else if is_range(ident) {
-                            parse_range(ident)
-                        } else {
-                            match find(contexts, ident) {
-                                Some(value) => Ok(value),
-                                None => Ok(Value::Null),
+                    AstKind::Projection { ref rest, optional, .. } => {
+                        let items = match object {
+                            Value::Array(items) => items,
+                            Value::Null => return Ok(Value::Null),
+                            _ if optional => return Ok(Value::Null),
+                            _ => return Err(Error::ExpectedArray),
+                        };
+                        let mut values = Vec::new();
+                        for item in items {
+                            match project(rest, item, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))? {
+                                Value::Null => (),
+                                // A nested projection yields an array per item, flattened into one.
+                                Value::Array(nested) if matches!(rest.kind, AstKind::Projection { .. }) => values.extend(nested),
+                                value => values.push(value),
                             }
                         }
+                        (Ok(Value::Array(values)), optional)
                     }
-                    _ => Err(Error::CanNotExec(node.operator.clone())),
+                    _ => return exec_node(ast, builtin, patterns, contexts, scope, functions, const_functions),
+                };
+                match result {
+                    Err(_) if optional => Ok(Value::Null),
+                    result => result,
+                }
+            }
+
+            /// Evaluates the `rest` of a projection with `item` standing in for `Current`.
+            #[rustfmt::skip]
+            #[allow(clippy::too_many_arguments)]
+            fn project(ast: &Ast,
+                       item: Value,
+                       builtin: &Functions,
+                       patterns: &Patterns,
+                       contexts: &[Context],
+                       scope: &Scope,
+                       functions: &Functions,
+                       const_functions: Rc<RefCell<ConstFunctions>>,)
+                       -> Result<Value, Error> {
+                match ast.kind {
+                    AstKind::Current => Ok(item),
+                    AstKind::Member { ref object, .. } |
+                    AstKind::Index { ref object, .. } |
+                    AstKind::Slice { ref object, .. } |
+                    AstKind::Method { ref object, .. } |
+                    AstKind::Projection { ref object, .. } => {
+                        let object = project(object, item, builtin, patterns, contexts, scope, functions, Rc::clone(&const_functions))?;
+                        postfix(ast, object, builtin, patterns, contexts, scope, functions, const_functions)
+                            .map_err(|error| error.at(ast.span))
+                    }
+                    _ => exec_node(ast, builtin, patterns, contexts, scope, functions, const_functions),
                 }
             }
             },
@@ -459,223 +308,179 @@ impl Tree {
     }
 }
 
-fn append_value_to_last_node(
-    parsing_nodes: &mut Vec<Node>,
-    operator: &Operator,
-) -> Result<(), Error> {
-    let mut node = operator.to_node();
-    node.closed = true;
+fn check_function_args(function: &Function, args_length: usize) -> Result<(), Error> {
+    if let Some(len) = function.max_args {
+        if args_length > len {
+            return Err(Error::ArgumentsGreater(len));
+        }
+    }
 
-    if let Some(mut prev) = parsing_nodes.pop() {
-        if prev.is_dot() {
-            prev.add_child(node);
-            prev.closed = true;
-            parsing_nodes.push(prev);
-        } else if prev.is_left_square_bracket() {
-            parsing_nodes.push(prev);
-            parsing_nodes.push(node);
-        } else if prev.is_value_or_full_children() {
-            return Err(Error::DuplicateValueNode);
-        } else if prev.is_enough() {
-            parsing_nodes.push(prev);
-            parsing_nodes.push(node);
-        } else if prev.operator.can_have_child() {
-            prev.add_child(node);
-            parsing_nodes.push(prev);
-        } else {
-            return Err(Error::CanNotAddChild);
+    if let Some(len) = function.min_args {
+        if args_length < len {
+            return Err(Error::ArgumentsLess(len));
         }
-    } else {
-        parsing_nodes.push(node);
     }
 
     Ok(())
 }
 
-fn get_final_node(mut parsing_nodes: Vec<Node>) -> Result<Node, Error> {
-    if parsing_nodes.is_empty() {
-        return Err(Error::NoFinalNode);
-    }
+fn has_function(name: &str, builtin: &Functions, functions: &Functions, const_functions: &RefCell<ConstFunctions>)
+    -> bool {
+    functions.contains_key(name) || builtin.contains_key(name) || const_functions.borrow().contains_key(name)
+}
 
-    while parsing_nodes.len() != 1 {
-        let last = parsing_nodes.pop().unwrap();
-        let mut prev = parsing_nodes.pop().unwrap();
-        if prev.operator.can_have_child() {
-            prev.add_child(last);
-            parsing_nodes.push(prev);
-        } else {
-            return Err(Error::CanNotAddChild);
-        }
+/// Calls the function `name`, looked up in `functions`, then `builtin`, then `const_functions`.
+fn call(name: &str,
+        values: Vec<Value>,
+        builtin: &Functions,
+        functions: &Functions,
+        const_functions: &RefCell<ConstFunctions>)
+        -> Result<Value, Error> {
+    if let Some(function) = functions.get(name).or_else(|| builtin.get(name)) {
+        check_function_args(function, values.len())?;
+        (function.compiled)(values)
+    } else if let Some(function) = const_functions.borrow().get(name) {
+        (function.compiled)(values)
+    } else {
+        Err(Error::FunctionNotExists(name.to_owned()))
     }
-
-    Ok(parsing_nodes.pop().unwrap())
 }
 
-fn close_bracket(parsing_nodes: &mut Vec<Node>, bracket: Operator) -> Result<(), Error> {
-    loop {
-        let mut current = parsing_nodes.pop().unwrap();
-        let mut prev = parsing_nodes.pop().unwrap();
-
-        if current.operator.is_left_square_bracket() {
-            return Err(Error::BracketNotWithFunction);
-        } else if prev.operator.is_left_square_bracket() {
-            prev.add_child(current);
-            prev.closed = true;
-            parsing_nodes.push(prev);
-            break;
-        } else if current.operator == bracket {
-            if prev.is_unclosed_function() {
-                prev.closed = true;
-                parsing_nodes.push(prev);
-                break;
-            } else {
-                return Err(Error::BracketNotWithFunction);
-            }
-        } else if prev.operator == bracket {
-            if !current.closed {
-                current.closed = true;
-            }
-
-            if let Some(mut p) = parsing_nodes.pop() {
-                if p.is_unclosed_function() {
-                    p.closed = true;
-                    p.add_child(current);
-                    parsing_nodes.push(p);
-                } else if p.is_unclosed_arithmetic() {
-                    p.add_child(current);
-                    parsing_nodes.push(p);
-                } else {
-                    parsing_nodes.push(p);
-                    parsing_nodes.push(current);
-                }
-            } else {
-                parsing_nodes.push(current);
-            }
-            break;
-        } else if !prev.closed {
-            prev.add_child(current);
-            if prev.is_enough() {
-                prev.closed = true;
-            }
-
-            if !parsing_nodes.is_empty() {
-                parsing_nodes.push(prev);
-            } else {
-                return Err(Error::StartWithNonValueOperator);
-            }
-        } else {
-            return Err(Error::StartWithNonValueOperator);
-        }
+fn regex(patterns: &Patterns, lhs: &Value, rhs: &Value) -> Result<Regex, Error> {
+    match *rhs {
+        Value::String(ref pattern) => patterns.get(pattern),
+        _ => Err(Error::UnsupportedTypes(format!("{:?}", lhs), format!("{:?}", rhs))),
     }
-
-    Ok(())
 }
 
-fn close_comma(parsing_nodes: &mut Vec<Node>) -> Result<(), Error> {
-    if parsing_nodes.len() < 2 {
-        return Err(Error::CommaNotWithFunction);
+/// Text of a template placeholder: strings as they are, anything else as JSON.
+pub(crate) fn stringify(value: &Value) -> String {
+    match *value {
+        Value::String(ref string) => string.clone(),
+        ref value => value.to_string(),
     }
+}
 
-    loop {
-        let current = parsing_nodes.pop().unwrap();
-        let mut prev = parsing_nodes.pop().unwrap();
-
-        if current.operator == Operator::Comma {
-            parsing_nodes.push(prev);
-            break;
-        } else if current.operator.is_left() {
-            parsing_nodes.push(prev);
-            parsing_nodes.push(current);
-            break;
-        } else if prev.operator.is_left() {
-            if let Some(mut p) = parsing_nodes.pop() {
-                if p.is_unclosed_function() {
-                    p.add_child(current);
-                    parsing_nodes.push(p);
-                    parsing_nodes.push(prev);
-                    break;
-                } else {
-                    return Err(Error::CommaNotWithFunction);
-                }
-            } else {
-                return Err(Error::CommaNotWithFunction);
-            }
-        } else if !prev.closed {
-            prev.add_child(current);
-            if prev.is_enough() {
-                prev.closed = true;
-            }
+fn member(object: &Value, name: &str) -> Result<Value, Error> {
+    match *object {
+        Value::Object(ref object) => Ok(object.get(name).cloned().unwrap_or(Value::Null)),
+        Value::Null => Ok(Value::Null),
+        _ => Err(Error::ExpectedObject),
+    }
+}
 
-            if !parsing_nodes.is_empty() {
-                parsing_nodes.push(prev);
-            } else {
-                return Err(Error::StartWithNonValueOperator);
+fn index_access(value: &Value, index: &Value) -> Result<Value, Error> {
+    let value = match *value {
+        Value::Object(ref object) => match index.as_str() {
+            Some(key) => object.get(key).cloned(),
+            None => return Err(Error::ExpectedIdentifier),
+        },
+        Value::Array(ref array) => match index.as_i64() {
+            Some(index) => position(index, array.len()).and_then(|index| array.get(index)).cloned(),
+            None => return Err(Error::ExpectedNumber),
+        },
+        Value::String(ref string) => match index.as_i64() {
+            Some(index) => {
+                let len = string.chars().count();
+                position(index, len)
+                    .and_then(|index| string.chars().nth(index))
+                    .map(|c| Value::String(c.to_string()))
             }
-        } else {
-            return Err(Error::StartWithNonValueOperator);
-        }
-    }
-    Ok(())
+            None => return Err(Error::ExpectedNumber),
+        },
+        Value::Null => None,
+        _ => return Err(Error::ExpectedArray),
+    };
+    Ok(value.unwrap_or(Value::Null))
 }
 
-fn rob_to(mut was_robed: Node, mut robber: Node) -> Vec<Node> {
-    let move_out_node = was_robed.move_out_last_node();
-    robber.add_child(move_out_node);
-    vec![was_robed, robber]
+/// Position of `index` in a sequence of `len` items, counting from the end when negative.
+fn position(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    if index < 0 { None } else { Some(index as usize) }
 }
 
-fn find(contexts: &[Context], key: &str) -> Option<Value> {
-    for context in contexts.iter().rev() {
-        match context.get(key) {
-            Some(value) => return Some(value.clone()),
-            None => continue,
-        }
+/// Python-style slice of an array or of the characters of a string. Null bounds are omitted.
+fn slice(value: &Value, start: &Value, end: &Value, step: &Value) -> Result<Value, Error> {
+    let bound = |bound: &Value| match *bound {
+        Value::Null => Ok(None),
+        ref bound => bound.as_i64().map(Some).ok_or(Error::ExpectedInteger),
+    };
+    let (start, end) = (bound(start)?, bound(end)?);
+    let step = bound(step)?.unwrap_or(1);
+    if step == 0 {
+        return Err(Error::ZeroStep);
     }
 
-    None
+    match *value {
+        Value::Array(ref array) => {
+            let positions = slice_positions(array.len(), start, end, step);
+            Ok(Value::Array(positions.map(|index| array[index].clone()).collect()))
+        }
+        Value::String(ref string) => {
+            let chars = string.chars().collect::<Vec<_>>();
+            let positions = slice_positions(chars.len(), start, end, step);
+            Ok(Value::String(positions.map(|index| chars[index]).collect()))
+        }
+        Value::Null => Ok(Value::Null),
+        _ => Err(Error::ExpectedArray),
+    }
 }
 
-fn is_range(ident: &str) -> bool {
-    ident.contains("..")
+fn slice_positions(len: usize, start: Option<i64>, end: Option<i64>, step: i64)
+    -> impl Iterator<Item = usize> {
+    let len = len as i64;
+    // Bounds are clamped to `lower..=upper`; a backwards slice may stop before the first item.
+    let (lower, upper) = if step > 0 { (0, len) } else { (-1, len - 1) };
+    let clamp = |bound: i64| {
+        if bound < 0 { (bound + len).max(lower) } else { bound.min(upper) }
+    };
+    let start = start.map_or(if step > 0 { lower } else { upper }, clamp);
+    let end = end.map_or(if step > 0 { upper } else { lower }, clamp);
+
+    // `None` once the next position would overflow, which is past the end anyway.
+    let mut index = Some(start);
+    std::iter::from_fn(move || {
+        let current = index.filter(|&index| step > 0 && index < end || step < 0 && index > end)?;
+        index = current.checked_add(step);
+        Some(current as usize)
+    })
 }
 
-fn parse_range(ident: &str) -> Result<Value, Error> {
-    let segments = ident.split("..").collect::<Vec<_>>();
-    if segments.len() != 2 {
-        Err(Error::InvalidRange(ident.to_owned()))
-    } else {
-        let start = segments[0].parse::<i64>();
-        let end = segments[1].parse::<i64>();
+/// Compiled expression that also takes the local variables in scope.
+pub(crate) type ScopedCompiled =
+    Box<dyn Fn(&[Context], &Scope, &Functions, Rc<RefCell<ConstFunctions>>) -> Result<Value, Error>>;
+
+/// Local variables, the innermost scope first. A scope borrows its parent, so entering one never
+/// copies the contexts.
+pub(crate) enum Scope<'a> {
+    Empty,
+    Local {
+        /// Later bindings shadow earlier ones.
+        bindings: &'a [(&'a str, Value)],
+        parent: &'a Scope<'a>,
+    },
+}
 
-        match (start, end) {
-            (Ok(start), Ok(end)) => {
-                let mut array = Vec::new();
-                for n in start..end {
-                    array.push(n);
-                }
-                Ok(to_value(array))
-            }
-            _ => {
-                Err(Error::InvalidRange(ident.to_owned()))
+impl<'a> Scope<'a> {
+    fn get(&self, key: &str) -> Option<&Value> {
+        let mut scope = self;
+        while let Scope::Local { bindings, parent } = *scope {
+            if let Some(binding) = bindings.iter().rev().find(|binding| binding.0 == key) {
+                return Some(&binding.1);
             }
+            scope = parent;
         }
+        None
     }
 }
 
-fn parse_number(ident: &str) -> Option<Value> {
-    let number = ident.parse::<u64>();
-    if let Ok(n) = number {
-        return Some(to_value(n));
-    }
-
-    let number = ident.parse::<i64>();
-    if let Ok(n) = number {
-        return Some(to_value(n));
-    }
-
-    let number = ident.parse::<f64>();
-    if let Ok(n) = number {
-        return Some(to_value(n));
+fn find(contexts: &[Context], key: &str) -> Option<Value> {
+    for context in contexts.iter().rev() {
+        match context.get(key) {
+            Some(value) => return Some(value.clone()),
+            None => continue,
+        }
     }
 
     None